@@ -1,5 +1,6 @@
 use std::{
     io::Error as IoError,
+    mem,
     pin::Pin,
     task::{ready, Context, Poll},
 };
@@ -8,20 +9,30 @@ use futures_util::stream::Stream;
 use hyper::body::{Bytes, Frame};
 
 use crate::{
-    util::{FileBytesStream, FileBytesStreamMultiRange, FileBytesStreamRange},
+    util::{
+        CompressedStream, DecompressedStream, FileBytesStream, FileBytesStreamMultiRange,
+        FileBytesStreamRange,
+    },
     vfs::{FileAccess, TokioFileAccess},
 };
 
 /// Hyper Body implementation for the various types of streams used in static serving.
+#[non_exhaustive]
 pub enum Body<F = TokioFileAccess> {
     /// No response body.
     Empty,
+    /// Serve an in-memory buffer, such as a generated page.
+    Bytes(Bytes),
     /// Serve a complete file.
     Full(FileBytesStream<F>),
     /// Serve a range from a file.
     Range(FileBytesStreamRange<F>),
     /// Serve multiple ranges from a file.
     MultiRange(FileBytesStreamMultiRange<F>),
+    /// Serve a complete file, compressed on the fly.
+    Compressed(CompressedStream<F>),
+    /// Serve a complete file that is stored encoded, decompressed on the fly.
+    Decompressed(DecompressedStream<F>),
 }
 
 impl<F: FileAccess> hyper::body::Body for Body<F> {
@@ -34,9 +45,17 @@ impl<F: FileAccess> hyper::body::Body for Body<F> {
     ) -> Poll<Option<Result<Frame<Bytes>, IoError>>> {
         let opt = ready!(match *self {
             Body::Empty => return Poll::Ready(None),
+            Body::Bytes(ref mut data) => {
+                if data.is_empty() {
+                    return Poll::Ready(None);
+                }
+                return Poll::Ready(Some(Ok(Frame::data(mem::take(data)))));
+            }
             Body::Full(ref mut stream) => Pin::new(stream).poll_next(cx),
             Body::Range(ref mut stream) => Pin::new(stream).poll_next(cx),
             Body::MultiRange(ref mut stream) => Pin::new(stream).poll_next(cx),
+            Body::Compressed(ref mut stream) => Pin::new(stream).poll_next(cx),
+            Body::Decompressed(ref mut stream) => Pin::new(stream).poll_next(cx),
         });
         Poll::Ready(opt.map(|res| res.map(Frame::data)))
     }
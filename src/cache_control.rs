@@ -0,0 +1,257 @@
+use std::{fmt, path::Path};
+
+use mime_guess::Mime;
+
+use crate::{compression::mime_matches, util::Glob, ResolvedFile};
+
+/// A `Cache-Control` response header value.
+///
+/// Directives are set using the builder pattern, and the header is formatted using `Display`. An
+/// empty value results in no header.
+///
+/// ```rust
+/// use hyper_staticfile::CacheControl;
+///
+/// let policy = CacheControl::new().public().max_age(31_536_000).immutable();
+/// assert_eq!(policy.to_string(), "public, max-age=31536000, immutable");
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CacheControl {
+    /// The `public` directive, allowing shared caches to store the response.
+    pub public: bool,
+    /// The `private` directive, allowing only the client to store the response.
+    pub private: bool,
+    /// The `no-cache` directive, requiring revalidation before each use.
+    pub no_cache: bool,
+    /// The `no-store` directive, forbidding caches to store the response.
+    pub no_store: bool,
+    /// The `max-age` directive, in seconds.
+    pub max_age: Option<u32>,
+    /// The `s-maxage` directive for shared caches, in seconds.
+    pub s_maxage: Option<u32>,
+    /// The `stale-while-revalidate` directive, in seconds.
+    pub stale_while_revalidate: Option<u32>,
+    /// The `must-revalidate` directive, forbidding use of the response once stale.
+    pub must_revalidate: bool,
+    /// The `immutable` directive, indicating the response will not change while fresh.
+    pub immutable: bool,
+}
+
+impl CacheControl {
+    /// Create an empty value, with no directives.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the `public` directive, and remove `private`.
+    pub fn public(mut self) -> Self {
+        self.public = true;
+        self.private = false;
+        self
+    }
+
+    /// Add the `private` directive, and remove `public`.
+    pub fn private(mut self) -> Self {
+        self.private = true;
+        self.public = false;
+        self
+    }
+
+    /// Add the `no-cache` directive.
+    pub fn no_cache(mut self) -> Self {
+        self.no_cache = true;
+        self
+    }
+
+    /// Add the `no-store` directive.
+    pub fn no_store(mut self) -> Self {
+        self.no_store = true;
+        self
+    }
+
+    /// Set the `max-age` directive, in seconds.
+    pub fn max_age(mut self, seconds: u32) -> Self {
+        self.max_age = Some(seconds);
+        self
+    }
+
+    /// Set the `s-maxage` directive, in seconds.
+    pub fn s_maxage(mut self, seconds: u32) -> Self {
+        self.s_maxage = Some(seconds);
+        self
+    }
+
+    /// Set the `stale-while-revalidate` directive, in seconds.
+    pub fn stale_while_revalidate(mut self, seconds: u32) -> Self {
+        self.stale_while_revalidate = Some(seconds);
+        self
+    }
+
+    /// Add the `must-revalidate` directive.
+    pub fn must_revalidate(mut self) -> Self {
+        self.must_revalidate = true;
+        self
+    }
+
+    /// Add the `immutable` directive.
+    pub fn immutable(mut self) -> Self {
+        self.immutable = true;
+        self
+    }
+
+    /// Whether no directives are set.
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl fmt::Display for CacheControl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let flags = [
+            (self.public, "public"),
+            (self.private, "private"),
+            (self.no_cache, "no-cache"),
+            (self.no_store, "no-store"),
+        ];
+        let values = [
+            ("max-age", self.max_age),
+            ("s-maxage", self.s_maxage),
+            ("stale-while-revalidate", self.stale_while_revalidate),
+        ];
+        let trailing = [
+            (self.must_revalidate, "must-revalidate"),
+            (self.immutable, "immutable"),
+        ];
+
+        let mut sep = "";
+        for (_, name) in flags.iter().filter(|(set, _)| *set) {
+            write!(f, "{}{}", sep, name)?;
+            sep = ", ";
+        }
+        for (name, value) in values.iter() {
+            if let Some(value) = value {
+                write!(f, "{}{}={}", sep, name, value)?;
+                sep = ", ";
+            }
+        }
+        for (_, name) in trailing.iter().filter(|(set, _)| *set) {
+            write!(f, "{}{}", sep, name)?;
+            sep = ", ";
+        }
+        Ok(())
+    }
+}
+
+/// Condition of a `CachePolicy` rule.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CacheRule {
+    /// Matches paths, relative to the root, against a glob pattern.
+    Path(Glob),
+    /// Matches the file extension, without leading dot, case-insensitively.
+    Extension(String),
+    /// Matches the content type, either a full MIME type, such as `text/html`, or a top-level
+    /// type with a wildcard, such as `image/*`.
+    ContentType(String),
+}
+
+impl CacheRule {
+    /// Whether the rule matches a file, given its path and content type.
+    pub fn is_match(&self, path: &Path, content_type: Option<&str>) -> bool {
+        match self {
+            CacheRule::Path(glob) => glob.is_match_path(path),
+            CacheRule::Extension(ext) => path
+                .extension()
+                .and_then(|value| value.to_str())
+                .is_some_and(|value| value.eq_ignore_ascii_case(ext)),
+            CacheRule::ContentType(pattern) => content_type
+                .and_then(|value| value.parse::<Mime>().ok())
+                .is_some_and(|mime| mime_matches(pattern, &mime)),
+        }
+    }
+}
+
+/// Rules to select the `Cache-Control` header of files.
+///
+/// Rules are tried in order, and the first match determines the header. Files that match no rule
+/// use the default, if any. Rules apply to the path of the file that was resolved, such as
+/// `/docs/index.html` for a request for `/docs/`, without the suffix of pre-encoded files.
+///
+/// ```rust
+/// use hyper_staticfile::{CacheControl, CachePolicy};
+///
+/// let policy = CachePolicy::new()
+///     .path("/assets/**", CacheControl::new().public().max_age(31_536_000).immutable())
+///     .extension("html", CacheControl::new().no_cache())
+///     .content_type("image/*", CacheControl::new().public().max_age(86400))
+///     .otherwise(CacheControl::new().public().max_age(3600));
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct CachePolicy {
+    /// Rules, in order, with the header value to use for matching files.
+    pub rules: Vec<(CacheRule, CacheControl)>,
+    /// Header value to use for files that match no rule.
+    pub default: Option<CacheControl>,
+}
+
+impl CachePolicy {
+    /// Create a policy without rules, that sends no `Cache-Control` header.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a policy that sends `public, max-age=N` for all files.
+    ///
+    /// This is the behavior of `Static::cache_headers`.
+    pub fn max_age(seconds: u32) -> Self {
+        Self::new().otherwise(CacheControl::new().public().max_age(seconds))
+    }
+
+    /// Add a rule.
+    pub fn rule(mut self, rule: CacheRule, value: CacheControl) -> Self {
+        self.rules.push((rule, value));
+        self
+    }
+
+    /// Add a rule for paths matching a glob pattern.
+    pub fn path(self, pattern: impl Into<Glob>, value: CacheControl) -> Self {
+        self.rule(CacheRule::Path(pattern.into()), value)
+    }
+
+    /// Add a rule for files with an extension, given without leading dot.
+    pub fn extension(self, ext: impl Into<String>, value: CacheControl) -> Self {
+        self.rule(CacheRule::Extension(ext.into()), value)
+    }
+
+    /// Add a rule for files with a content type, such as `text/html` or `image/*`.
+    pub fn content_type(self, pattern: impl Into<String>, value: CacheControl) -> Self {
+        self.rule(CacheRule::ContentType(pattern.into()), value)
+    }
+
+    /// Set the header value for files that match no rule.
+    pub fn otherwise(mut self, value: CacheControl) -> Self {
+        self.default = Some(value);
+        self
+    }
+
+    /// Select the header value for a resolved file.
+    pub fn select<F>(&self, file: &ResolvedFile<F>) -> Option<&CacheControl> {
+        // Match pre-encoded files by the path of the original.
+        let mut path = file.path.as_path();
+        if let Some(enc) = file.encoding.as_ref().or(file.decode.as_ref()) {
+            if let Some(stem) = path
+                .to_str()
+                .and_then(|value| value.strip_suffix(enc.suffix()))
+            {
+                path = Path::new(stem);
+            }
+        }
+
+        let content_type = file.content_type.as_deref();
+        self.rules
+            .iter()
+            .find(|(rule, _)| rule.is_match(path, content_type))
+            .map(|(_, value)| value)
+            .or(self.default.as_ref())
+            .filter(|value| !value.is_empty())
+    }
+}
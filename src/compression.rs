@@ -0,0 +1,168 @@
+use mime_guess::Mime;
+
+use crate::{AcceptEncoding, Encoding};
+
+/// Compression level used for on-the-fly compression.
+///
+/// The meaning of levels differs per encoding, so the named levels are usually the best choice.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CompressionLevel {
+    /// Fastest compression, with the least reduction in size.
+    Fastest,
+    /// A balance between speed and size, suitable for compressing responses as they are sent.
+    #[default]
+    Default,
+    /// Best compression, at the cost of speed.
+    Best,
+    /// A specific level, clamped to the range the encoding supports.
+    Precise(i32),
+}
+
+impl CompressionLevel {
+    /// Resolve the level for a specific encoding.
+    pub(crate) fn for_encoding(&self, enc: &Encoding) -> i32 {
+        let (fastest, default, best, max) = match enc {
+            Encoding::Gzip => (1, 6, 9, 9),
+            // The brotli default of 11 is far too slow for compressing on the fly.
+            Encoding::Br => (0, 4, 11, 11),
+            // Levels above 19 require a lot of memory, so only use them when asked for.
+            Encoding::Zstd => (1, 3, 19, 22),
+            Encoding::Custom { .. } => (0, 0, 0, 0),
+        };
+        match *self {
+            CompressionLevel::Fastest => fastest,
+            CompressionLevel::Default => default,
+            CompressionLevel::Best => best,
+            CompressionLevel::Precise(level) => level.clamp(fastest, max),
+        }
+    }
+}
+
+/// Settings for compressing responses on the fly.
+///
+/// This applies to files served in full that have no pre-encoded variant. Responses for range
+/// requests are never compressed. Each encoding is only available if the crate feature of the same
+/// name is enabled. (`gzip`, `brotli` or `zstd`)
+///
+/// ```rust
+/// use hyper_staticfile::{Compression, CompressionLevel};
+///
+/// let compression = Compression::new()
+///     .min_size(1024)
+///     .level(CompressionLevel::Fastest)
+///     .content_type("application/wasm");
+/// ```
+#[derive(Clone, Debug)]
+pub struct Compression {
+    /// Encodings to use, in order of server preference.
+    ///
+    /// Custom encodings, and encodings for which the crate feature is not enabled, are ignored.
+    /// Defaults to `AcceptEncoding::all()`.
+    pub encodings: AcceptEncoding,
+    /// Content types to compress.
+    ///
+    /// Entries are either a full MIME type, such as `application/json`, or a top-level type with a
+    /// wildcard, such as `text/*`. Parameters such as `charset` are ignored when matching.
+    pub content_types: Vec<String>,
+    /// Minimum file size in bytes to compress. Defaults to 256.
+    pub min_size: u64,
+    /// Compression level. Defaults to `CompressionLevel::Default`.
+    pub level: CompressionLevel,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self {
+            encodings: AcceptEncoding::all(),
+            content_types: [
+                "text/*",
+                "application/javascript",
+                "application/json",
+                "application/manifest+json",
+                "application/xml",
+                "image/svg+xml",
+            ]
+            .iter()
+            .map(|s| s.to_string())
+            .collect(),
+            min_size: 256,
+            level: CompressionLevel::Default,
+        }
+    }
+}
+
+impl Compression {
+    /// Create compression settings with defaults for common text formats.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the encodings to use, in order of server preference.
+    pub fn encodings(mut self, value: AcceptEncoding) -> Self {
+        self.encodings = value;
+        self
+    }
+
+    /// Add a content type to compress.
+    pub fn content_type(mut self, value: impl Into<String>) -> Self {
+        self.content_types.push(value.into());
+        self
+    }
+
+    /// Set the minimum file size in bytes to compress.
+    pub fn min_size(mut self, value: u64) -> Self {
+        self.min_size = value;
+        self
+    }
+
+    /// Set the compression level.
+    pub fn level(mut self, value: CompressionLevel) -> Self {
+        self.level = value;
+        self
+    }
+
+    /// Whether files of the given content type should be compressed.
+    pub fn applies_to(&self, content_type: &str) -> bool {
+        let mime: Mime = match content_type.parse() {
+            Ok(mime) => mime,
+            Err(_) => return false,
+        };
+        self.content_types
+            .iter()
+            .any(|entry| mime_matches(entry, &mime))
+    }
+
+    /// Choose an encoding for a response, given the `Accept-Encoding` of the request.
+    ///
+    /// Returns `None` if the client prefers the identity encoding, or accepts none of the
+    /// available encodings.
+    pub fn negotiate(&self, accept_encoding: &AcceptEncoding) -> Option<Encoding> {
+        let accepted = self.encodings.clone() & accept_encoding.clone();
+        let identity = accepted.identity_quality().unwrap_or(0);
+        accepted
+            .preferred()
+            .into_iter()
+            .find(|(enc, q)| *q >= identity && is_available(enc))
+            .map(|(enc, _)| enc)
+    }
+}
+
+/// Whether a MIME type matches a full MIME type, or a top-level type with a wildcard.
+///
+/// Parameters such as `charset` are ignored.
+pub(crate) fn mime_matches(pattern: &str, mime: &Mime) -> bool {
+    match pattern.split_once('/') {
+        Some((type_, "*")) => mime.type_().as_str().eq_ignore_ascii_case(type_),
+        _ => mime.essence_str().eq_ignore_ascii_case(pattern),
+    }
+}
+
+/// Whether support for compressing with an encoding was compiled in.
+pub(crate) fn is_available(enc: &Encoding) -> bool {
+    match enc {
+        Encoding::Gzip => cfg!(feature = "gzip"),
+        Encoding::Br => cfg!(feature = "brotli"),
+        Encoding::Zstd => cfg!(feature = "zstd"),
+        Encoding::Custom { .. } => false,
+    }
+}
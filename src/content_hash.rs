@@ -0,0 +1,113 @@
+// Without any hash features, `Hasher` is uninhabited.
+#![cfg_attr(
+    not(any(feature = "xxh3", feature = "sha256")),
+    allow(unreachable_code, unused_imports, unused_variables, unused_mut)
+)]
+
+use std::{fmt::Write, io::Error as IoError, sync::Arc};
+
+use futures_util::stream::TryStreamExt;
+
+use crate::{util::FileBytesStream, vfs::FileAccess};
+
+/// Hash algorithm used to derive strong entity tags from file contents.
+///
+/// Each algorithm is only available if the crate feature of the same name is enabled. (`xxh3` or
+/// `sha256`) Without it, no hash is computed, and responses carry the default weak entity tag. Any
+/// of the algorithms is suitable for entity tags; xxh3 is much faster, while sha256 may be useful
+/// if the hashes are also used elsewhere.
+///
+/// ```rust
+/// use hyper_staticfile::{ContentHash, Resolver};
+///
+/// let mut resolver = Resolver::new("public/");
+/// resolver.content_hash = Some(ContentHash::Xxh3);
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ContentHash {
+    /// 128-bit xxh3, requires the `xxh3` feature.
+    Xxh3,
+    /// SHA-256, requires the `sha256` feature.
+    Sha256,
+}
+
+impl ContentHash {
+    /// Whether support for this algorithm was compiled in.
+    pub fn is_available(&self) -> bool {
+        match self {
+            ContentHash::Xxh3 => cfg!(feature = "xxh3"),
+            ContentHash::Sha256 => cfg!(feature = "sha256"),
+        }
+    }
+
+    /// Hash data in memory, returning the digest as lowercase hexadecimal.
+    ///
+    /// Returns `None` if the algorithm is not available.
+    pub fn hash(&self, data: &[u8]) -> Option<Arc<str>> {
+        let mut hasher = Hasher::new(*self)?;
+        hasher.update(data);
+        Some(hasher.finish())
+    }
+
+    /// Read a file to completion, and hash its contents.
+    pub(crate) async fn hash_file<F: FileAccess>(
+        &self,
+        file: F,
+        size: u64,
+    ) -> Result<Option<Arc<str>>, IoError> {
+        let mut hasher = match Hasher::new(*self) {
+            Some(hasher) => hasher,
+            None => return Ok(None),
+        };
+        let mut stream = FileBytesStream::new_with_limit(file, size);
+        while let Some(chunk) = stream.try_next().await? {
+            hasher.update(&chunk);
+        }
+        Ok(Some(hasher.finish()))
+    }
+}
+
+/// Incremental state of one of the hash algorithms.
+enum Hasher {
+    #[cfg(feature = "xxh3")]
+    Xxh3(Box<xxhash_rust::xxh3::Xxh3>),
+    #[cfg(feature = "sha256")]
+    Sha256(sha2::Sha256),
+}
+
+impl Hasher {
+    fn new(algo: ContentHash) -> Option<Self> {
+        match algo {
+            #[cfg(feature = "xxh3")]
+            ContentHash::Xxh3 => Some(Hasher::Xxh3(Box::default())),
+            #[cfg(feature = "sha256")]
+            ContentHash::Sha256 => Some(Hasher::Sha256(sha2::Digest::new())),
+            #[allow(unreachable_patterns)]
+            _ => None,
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match *self {
+            #[cfg(feature = "xxh3")]
+            Hasher::Xxh3(ref mut hasher) => hasher.update(data),
+            #[cfg(feature = "sha256")]
+            Hasher::Sha256(ref mut hasher) => sha2::Digest::update(hasher, data),
+        }
+    }
+
+    fn finish(self) -> Arc<str> {
+        let mut res = String::new();
+        match self {
+            #[cfg(feature = "xxh3")]
+            Hasher::Xxh3(hasher) => write!(res, "{:032x}", hasher.digest128()).unwrap(),
+            #[cfg(feature = "sha256")]
+            Hasher::Sha256(hasher) => {
+                for byte in sha2::Digest::finalize(hasher) {
+                    write!(res, "{:02x}", byte).unwrap();
+                }
+            }
+        }
+        res.into()
+    }
+}
@@ -65,12 +65,18 @@
 //!
 //! It's useful to sit between these two steps to implement custom 404 pages, for example. Your
 //! custom logic can override specific cases of `ResolveResult`, and fall back to the default
-//! behavior using `ResponseBuilder` if necessary.
+//! behavior using `ResponseBuilder` if necessary. (For simple static error pages, see
+//! `Resolver::set_error_document`.)
 
 mod body;
+mod cache_control;
+mod compression;
+mod content_hash;
+mod negative_cache;
 mod resolve;
 mod response_builder;
 mod service;
+mod validators;
 
 /// Lower level utilities.
 pub mod util;
@@ -78,6 +84,11 @@ pub mod util;
 pub mod vfs;
 
 pub use crate::body::Body;
+pub use crate::cache_control::*;
+pub use crate::compression::*;
+pub use crate::content_hash::*;
+pub use crate::negative_cache::*;
 pub use crate::resolve::*;
 pub use crate::response_builder::*;
 pub use crate::service::*;
+pub use crate::validators::*;
@@ -0,0 +1,138 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// Cache of paths the resolver found to be absent.
+///
+/// Resolving a request usually involves several lookups that find nothing, such as pre-encoded
+/// variants, files with one of `Resolver::try_suffixes`, and directory indexes. With the regular
+/// filesystem, each lookup is a round trip to a blocking thread. This cache remembers such misses
+/// for a limited time, so repeated requests skip them.
+///
+/// Only negative results are cached. Files that are found need an open file handle, which this
+/// cache can't provide. To also cache those, along with their metadata, wrap the opener in
+/// `vfs::HandleCacheFs`. Together, repeated requests for hot files need no blocking lookups at all.
+///
+/// Entries expire after the time-to-live, so new files are picked up eventually. To pick up new
+/// files immediately, for example from a filesystem watcher, call `invalidate` or `clear`. Note
+/// that this also applies to variants a `VariantCacheFs` generates in the background.
+///
+/// The cache is shared between clones of the resolver, and can be shared between resolvers with
+/// the same root:
+///
+/// ```rust
+/// use std::{sync::Arc, time::Duration};
+///
+/// use hyper_staticfile::{NegativeCache, Resolver};
+///
+/// let cache = Arc::new(NegativeCache::new(Duration::from_secs(10)));
+/// let mut resolver = Resolver::new("public/");
+/// resolver.negative_cache = Some(cache.clone());
+///
+/// // Later, when files change:
+/// cache.invalidate("assets".as_ref());
+/// ```
+#[derive(Debug)]
+pub struct NegativeCache {
+    ttl: Duration,
+    capacity: usize,
+    /// Absent paths, with the time their entry expires.
+    entries: Mutex<HashMap<PathBuf, Instant>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl NegativeCache {
+    /// Create a cache where entries expire after the given time-to-live.
+    ///
+    /// The cache holds at most 65536 entries by default. See `capacity`.
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            capacity: 65536,
+            entries: Mutex::default(),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Set the maximum number of entries.
+    ///
+    /// When the cache is full, expired entries are removed, and if that doesn't make room, new
+    /// entries are not added. This bounds memory use when clients request many missing paths.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Forget a path, and all paths below it.
+    pub fn invalidate(&self, path: &Path) {
+        self.entries
+            .lock()
+            .unwrap()
+            .retain(|entry, _| !entry.starts_with(path));
+    }
+
+    /// Forget all paths.
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+
+    /// Number of entries, including any that expired but were not removed yet.
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    /// Whether there are no entries.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of lookups answered from the cache.
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// Number of lookups passed on to the opener.
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    /// Check whether a path is known to be absent, counting a hit or miss.
+    pub(crate) fn is_absent(&self, path: &Path) -> bool {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        let absent = match entries.get(path) {
+            Some(expires) if *expires > now => true,
+            Some(_) => {
+                entries.remove(path);
+                false
+            }
+            None => false,
+        };
+        drop(entries);
+
+        let counter = if absent { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+        absent
+    }
+
+    /// Remember that a path is absent.
+    pub(crate) fn insert_absent(&self, path: &Path) {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= self.capacity {
+            entries.retain(|_, expires| *expires > now);
+            if entries.len() >= self.capacity {
+                return;
+            }
+        }
+        entries.insert(path.to_path_buf(), now + self.ttl);
+    }
+}
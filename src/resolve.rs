@@ -1,20 +1,25 @@
 use std::{
+    borrow::Cow,
+    collections::HashMap,
     future::Future,
     io::{Error as IoError, ErrorKind as IoErrorKind, Result as IoResult},
+    iter::FromIterator,
     ops::BitAnd,
-    path::PathBuf,
-    sync::Arc,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
     time::SystemTime,
 };
 
-use futures_util::future::BoxFuture;
-use http::{header, HeaderValue, Method, Request};
+use futures_util::{future::BoxFuture, lock::Mutex as AsyncMutex};
+use http::{header, HeaderValue, Method, Request, StatusCode};
 use mime_guess::{mime, Mime, MimeGuess};
 use tokio::fs::File;
 
 use crate::{
-    util::RequestedPath,
-    vfs::{FileOpener, FileWithMetadata, TokioFileOpener},
+    compression::is_available,
+    util::{directory_url_path, sanitize_path, Glob, RequestedPath},
+    vfs::{DirEntry, FileOpener, FileWithMetadata, IntoFileAccess, TokioFileOpener},
+    ContentHash, NegativeCache,
 };
 
 /// Struct containing all the required data to serve a file.
@@ -23,7 +28,7 @@ pub struct ResolvedFile<F = File> {
     /// Open file handle.
     pub handle: F,
     /// The resolved and sanitized path to the file.
-    /// For directory indexes, this includes `index.html`.
+    /// For directory indexes, this includes the index file name, such as `index.html`.
     /// For pre-encoded files, this will include the compressed extension. (`.gz`, `.br`, or `.zst`)
     pub path: PathBuf,
     /// Size in bytes.
@@ -34,6 +39,16 @@ pub struct ResolvedFile<F = File> {
     pub content_type: Option<String>,
     /// 'Content-Encoding' value.
     pub encoding: Option<Encoding>,
+    /// Whether the resolver negotiated the encoding, so other requests for the same path may
+    /// receive a different representation depending on `Accept-Encoding`.
+    pub vary_encoding: bool,
+    /// Encoding of the file contents, if they must be decompressed while serving.
+    ///
+    /// This is set for files that only exist in encoded form, requested by a client that doesn't
+    /// accept the encoding. (See `Resolver::encoded_only`.) In this case, `encoding` is `None`.
+    pub decode: Option<Encoding>,
+    /// Hash of the file contents, used for a strong entity tag. (See `Resolver::content_hash`.)
+    pub content_hash: Option<Arc<str>>,
 }
 
 impl<F> ResolvedFile<F> {
@@ -42,6 +57,7 @@ impl<F> ResolvedFile<F> {
         path: PathBuf,
         content_type: Option<String>,
         encoding: Option<Encoding>,
+        vary_encoding: bool,
     ) -> Self {
         Self {
             handle: file.handle,
@@ -50,8 +66,38 @@ impl<F> ResolvedFile<F> {
             modified: file.modified,
             content_type,
             encoding,
+            vary_encoding,
+            decode: None,
+            content_hash: file.content_hash,
         }
     }
+
+    /// Split off the file handle, leaving only the metadata.
+    pub(crate) fn split_handle(self) -> (F, ResolvedFile<()>) {
+        let meta = ResolvedFile {
+            handle: (),
+            path: self.path,
+            size: self.size,
+            modified: self.modified,
+            content_type: self.content_type,
+            encoding: self.encoding,
+            vary_encoding: self.vary_encoding,
+            decode: self.decode,
+            content_hash: self.content_hash,
+        };
+        (self.handle, meta)
+    }
+}
+
+/// Struct containing all the required data to render a directory listing.
+#[derive(Debug)]
+pub struct DirectoryListing {
+    /// The resolved and sanitized path to the directory.
+    pub path: PathBuf,
+    /// Entries in the directory, with directories first, then sorted by name.
+    pub entries: Vec<DirEntry>,
+    /// Encodings of pre-encoded variants to report, from `Resolver::allowed_encodings`.
+    pub encodings: Vec<Encoding>,
 }
 
 /// Resolves request paths to files.
@@ -70,15 +116,239 @@ pub struct Resolver<O = TokioFileOpener> {
     /// This only supports pre-encoded files, that exist adjacent to the original file, but with an
     /// additional `.gz`, `.br`, or `.zst` suffix (after the original extension).
     ///
-    /// Typically initialized with `AcceptEncoding::all()` or `AcceptEncoding::none()`.
+    /// Typically initialized with `AcceptEncoding::all()` or `AcceptEncoding::none()`. The order of
+    /// encodings is the server preference, used when the client accepts several encodings with
+    /// the same quality value.
     pub allowed_encodings: AcceptEncoding,
 
+    /// File names to try, in order, when a directory is requested.
+    ///
+    /// Defaults to just `index.html`. See `set_index_files` for a convenience setter.
+    pub index_files: Arc<[String]>,
+
+    /// File name suffixes to try, in order, when the exact path is not found.
+    ///
+    /// This is similar to `try_files $uri $uri.html $uri/` in nginx, with a suffix of `.html`.
+    /// Files found this way take precedence over a directory of the same name. Defaults to no
+    /// suffixes. See `set_try_suffixes` for a convenience setter.
+    pub try_suffixes: Arc<[String]>,
+
+    /// Whether to redirect requests that include one of `try_suffixes` to the path without it.
+    ///
+    /// For example, with a `.html` suffix, `/about.html` redirects to `/about`.
+    pub canonical_redirect: bool,
+
+    /// Whether to redirect requests for a directory without trailing slash.
+    ///
+    /// When enabled (the default), such requests result in `ResolveResult::IsDirectory`. When
+    /// disabled, the directory index is served in place. Note that relative links in the index
+    /// then resolve against the parent directory. Generated listings use absolute links, so are not
+    /// affected. (See `autoindex`.)
+    pub redirect_directories: bool,
+
+    /// Whether to redirect requests for a file with trailing slash to the path without it.
+    ///
+    /// When disabled (the default), such requests result in `ResolveResult::NotFound`.
+    pub strip_trailing_slash: bool,
+
+    /// Whether to list the contents of directories that have no index file.
+    ///
+    /// When disabled, such directory requests result in `ResolveResult::NotFound`. This is also
+    /// the case if the opener does not support listings. (See `FileOpener::read_dir`.)
+    pub autoindex: bool,
+
+    /// Whether to serve files that only exist in encoded form, such as `app.js.gz` without
+    /// `app.js`.
+    ///
+    /// This also applies to directory index files. Only encodings in `allowed_encodings` are
+    /// tried. Clients that accept the encoding receive the file as-is, while other clients receive
+    /// it decompressed on the fly. Decompression requires the crate feature for the encoding.
+    /// (`gzip`, `brotli` or `zstd`) Defaults to disabled.
+    pub encoded_only: bool,
+
+    /// How to treat path components that start with a dot, such as `.git` or `.env`.
+    ///
+    /// This applies to every path the resolver opens, including directory indexes and
+    /// pre-encoded variants, and hides such entries from directory listings. Defaults to
+    /// `DotfilePolicy::Allow`.
+    pub dotfiles: DotfilePolicy,
+
+    /// Path components that are exempt from the dotfile policy.
+    ///
+    /// Defaults to just `.well-known`. See `set_dotfile_exemptions` for a convenience setter.
+    pub dotfile_exemptions: Arc<[String]>,
+
+    /// Optional document to serve when a file is not found.
+    ///
+    /// See `Fallback` for details.
+    pub fallback: Option<Arc<Fallback>>,
+
+    /// Documents to serve for error responses, by status code, relative to the root.
+    ///
+    /// Supported are `400 Bad Request` (for `MethodNotMatched`), `403 Forbidden` (for
+    /// `PermissionDenied`) and `404 Not Found`. See `set_error_document` for a convenience setter.
+    pub error_documents: Arc<HashMap<StatusCode, PathBuf>>,
+
+    /// Algorithm to hash file contents with, for strong entity tags.
+    ///
+    /// Unlike the default weak entity tags, which are derived from size and modification time,
+    /// these are the same across servers that have the same files, and can be used with
+    /// `If-Range`. A file is hashed when it is first served, which requires reading it in full,
+    /// and the result is cached by path, size and modification time, for up to 65536 files.
+    /// Concurrent requests for the same file wait for a single hash. If hashing fails, the file is
+    /// served with the default weak entity tag. Files without a modification time are hashed on
+    /// every request. Hashes precomputed by the opener take precedence, see
+    /// `MemoryFs::precompute_hashes`. Defaults to `None`.
+    pub content_hash: Option<ContentHash>,
+
+    /// Optional cache of paths found to be absent, to avoid repeated lookups.
+    ///
+    /// See `NegativeCache` for details. Defaults to `None`.
+    pub negative_cache: Option<Arc<NegativeCache>>,
+
     /// Optional function that can rewrite requests.
     ///
     /// This function is called after parsing the request and before querying the filesystem.
     ///
     /// See `set_rewrite` for a convenience setter that simplifies these types.
     pub rewrite: Option<Arc<dyn (Fn(ResolveParams) -> BoxRewriteFuture) + Send + Sync>>,
+
+    /// Content hashes computed so far, shared between clones.
+    hash_cache: Arc<Mutex<HashCache>>,
+}
+
+/// Maximum number of files to cache content hashes for.
+const HASH_CACHE_CAPACITY: usize = 65536;
+
+/// Content hashes computed by the resolver. See `Resolver::content_hash`.
+#[derive(Default)]
+struct HashCache {
+    /// Hashes by path, with the size and modification time of the file that was hashed.
+    entries: HashMap<PathBuf, (u64, SystemTime, Arc<str>)>,
+    /// Paths currently being hashed, with a lock held by the request doing the work.
+    pending: HashMap<PathBuf, Arc<AsyncMutex<()>>>,
+}
+
+impl HashCache {
+    fn get(&self, path: &Path, size: u64, modified: SystemTime) -> Option<Arc<str>> {
+        match self.entries.get(path) {
+            Some((cached_size, cached_modified, hash))
+                if *cached_size == size && *cached_modified == modified =>
+            {
+                Some(hash.clone())
+            }
+            _ => None,
+        }
+    }
+
+    fn insert(&mut self, path: PathBuf, size: u64, modified: SystemTime, hash: Arc<str>) {
+        // When full, make room by evicting an arbitrary entry.
+        if self.entries.len() >= HASH_CACHE_CAPACITY && !self.entries.contains_key(&path) {
+            if let Some(evict) = self.entries.keys().next().cloned() {
+                self.entries.remove(&evict);
+            }
+        }
+        self.entries.insert(path, (size, modified, hash));
+    }
+}
+
+/// Removes a path from `HashCache::pending` when hashing finishes or is cancelled.
+struct PendingHash {
+    cache: Arc<Mutex<HashCache>>,
+    path: PathBuf,
+    lock: Arc<AsyncMutex<()>>,
+}
+
+impl Drop for PendingHash {
+    fn drop(&mut self) {
+        // Don't panic while already panicking.
+        if let Ok(mut guard) = self.cache.lock() {
+            // A later request may have started hashing a newer version of the file.
+            if matches!(guard.pending.get(&self.path), Some(lock) if Arc::ptr_eq(lock, &self.lock))
+            {
+                guard.pending.remove(&self.path);
+            }
+        }
+    }
+}
+
+/// Policy for path components that start with a dot. See `Resolver::dotfiles`.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum DotfilePolicy {
+    /// Serve dotfiles like any other file.
+    #[default]
+    Allow,
+    /// Refuse access to dotfiles, resulting in `ResolveResult::PermissionDenied`.
+    Deny,
+    /// Pretend dotfiles don't exist, resulting in `ResolveResult::NotFound`.
+    Ignore,
+}
+
+/// Settings for serving a fallback document when a file is not found.
+///
+/// This is typically used for single-page applications, where client-side routing handles any
+/// path, and the server must respond with `/index.html` for all of them. Unlike a rewrite, the
+/// fallback only applies after the filesystem lookup found nothing.
+///
+/// ```rust
+/// use hyper_staticfile::Fallback;
+///
+/// let fallback = Fallback::new("/index.html").exclude("/api/**");
+/// ```
+#[derive(Clone, Debug)]
+pub struct Fallback {
+    /// Path of the document to serve, relative to the root.
+    pub document: PathBuf,
+    /// Whether requests for paths with a file extension skip the fallback, and remain a 404.
+    ///
+    /// This is enabled by default, because such requests are usually for missing assets.
+    pub skip_extensions: bool,
+    /// Request paths matching any of these patterns always use the fallback, even when they have
+    /// a file extension.
+    pub include: Vec<Glob>,
+    /// Request paths matching any of these patterns never use the fallback.
+    pub exclude: Vec<Glob>,
+}
+
+impl Fallback {
+    /// Create fallback settings for the given document.
+    pub fn new(document: impl Into<PathBuf>) -> Self {
+        Self {
+            document: document.into(),
+            skip_extensions: true,
+            include: Vec::new(),
+            exclude: Vec::new(),
+        }
+    }
+
+    /// Set whether requests for paths with a file extension skip the fallback.
+    pub fn skip_extensions(mut self, value: bool) -> Self {
+        self.skip_extensions = value;
+        self
+    }
+
+    /// Add a pattern of request paths that always use the fallback.
+    pub fn include(mut self, pattern: impl Into<Glob>) -> Self {
+        self.include.push(pattern.into());
+        self
+    }
+
+    /// Add a pattern of request paths that never use the fallback.
+    pub fn exclude(mut self, pattern: impl Into<Glob>) -> Self {
+        self.exclude.push(pattern.into());
+        self
+    }
+
+    /// Whether the fallback applies to the given sanitized request path.
+    pub fn applies_to(&self, path: &Path) -> bool {
+        if self.exclude.iter().any(|glob| glob.is_match_path(path)) {
+            return false;
+        }
+        if self.include.iter().any(|glob| glob.is_match_path(path)) {
+            return true;
+        }
+        !self.skip_extensions || path.extension().is_none()
+    }
 }
 
 /// Future returned by a rewrite function. See `Resolver::set_rewrite`.
@@ -104,6 +374,7 @@ pub struct ResolveParams {
 ///
 /// Covers all the possible 'normal' scenarios encountered when serving static files.
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum ResolveResult<F = File> {
     /// The request was not `GET` or `HEAD` request,
     MethodNotMatched,
@@ -116,8 +387,22 @@ pub enum ResolveResult<F = File> {
         /// Path to redirect to.
         redirect_to: String,
     },
+    /// A file was requested by a non-canonical path, such as with a trailing slash.
+    Redirect {
+        /// Path to redirect to.
+        redirect_to: String,
+    },
     /// The requested file was found.
     Found(ResolvedFile<F>),
+    /// The request failed, and an error document was found to serve instead.
+    ErrorDocument {
+        /// Status code of the error.
+        status: StatusCode,
+        /// The error document.
+        file: ResolvedFile<F>,
+    },
+    /// A directory without index was requested, and `autoindex` is enabled.
+    Listing(DirectoryListing),
 }
 
 /// Some IO errors are expected when serving files, and mapped to a regular result here.
@@ -129,6 +414,17 @@ fn map_open_err<F>(err: IoError) -> IoResult<ResolveResult<F>> {
     }
 }
 
+/// Build the path to redirect to for a sanitized path.
+fn redirect_target(path: &Path, is_dir: bool) -> String {
+    // Components are percent-encoded, so characters such as `?` and `%` in file names don't
+    // change the meaning of the URL.
+    let mut target = directory_url_path(path, true);
+    if !is_dir && target.len() > 1 {
+        target.pop();
+    }
+    target
+}
+
 impl Resolver<TokioFileOpener> {
     /// Create a resolver that resolves files inside a root directory on the regular filesystem.
     pub fn new(root: impl Into<PathBuf>) -> Self {
@@ -142,10 +438,103 @@ impl<O: FileOpener> Resolver<O> {
         Self {
             opener: Arc::new(opener),
             allowed_encodings: AcceptEncoding::none(),
+            index_files: Arc::new(["index.html".to_string()]),
+            try_suffixes: Arc::new([]),
+            canonical_redirect: false,
+            redirect_directories: true,
+            strip_trailing_slash: false,
+            autoindex: false,
+            encoded_only: false,
+            dotfiles: DotfilePolicy::Allow,
+            dotfile_exemptions: Arc::new([".well-known".to_string()]),
+            fallback: None,
+            error_documents: Arc::new(HashMap::new()),
+            content_hash: None,
+            negative_cache: None,
             rewrite: None,
+            hash_cache: Arc::default(),
         }
     }
 
+    /// Configure the file names to try, in order, when a directory is requested.
+    ///
+    /// ```rust
+    /// let mut resolver = hyper_staticfile::Resolver::new("/");
+    /// resolver.set_index_files(["index.html", "index.htm", "default.html"]);
+    /// ```
+    pub fn set_index_files<I, S>(&mut self, files: I) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.index_files = files.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Configure file name suffixes to try, in order, when the exact path is not found.
+    ///
+    /// ```rust
+    /// let mut resolver = hyper_staticfile::Resolver::new("/");
+    /// resolver.set_try_suffixes([".html"]);
+    /// ```
+    pub fn set_try_suffixes<I, S>(&mut self, suffixes: I) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.try_suffixes = suffixes.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Configure path components that are exempt from the dotfile policy.
+    ///
+    /// ```rust
+    /// use hyper_staticfile::DotfilePolicy;
+    ///
+    /// let mut resolver = hyper_staticfile::Resolver::new("/");
+    /// resolver.dotfiles = DotfilePolicy::Deny;
+    /// resolver.set_dotfile_exemptions([".well-known", ".nojekyll"]);
+    /// ```
+    pub fn set_dotfile_exemptions<I, S>(&mut self, names: I) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.dotfile_exemptions = names.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Configure a document to serve when a file is not found.
+    ///
+    /// ```rust
+    /// use hyper_staticfile::Fallback;
+    ///
+    /// let mut resolver = hyper_staticfile::Resolver::new("/");
+    /// resolver.set_fallback(Some(Fallback::new("/index.html")));
+    /// ```
+    pub fn set_fallback(&mut self, fallback: Option<Fallback>) -> &mut Self {
+        self.fallback = fallback.map(Arc::new);
+        self
+    }
+
+    /// Configure a document to serve for error responses with the given status code.
+    ///
+    /// The document is resolved like a regular file, and served with the error status code. If
+    /// the document itself is missing, the response has an empty body.
+    ///
+    /// ```rust
+    /// let mut resolver = hyper_staticfile::Resolver::new("/");
+    /// resolver.set_error_document(http::StatusCode::NOT_FOUND, "/404.html");
+    /// ```
+    pub fn set_error_document(
+        &mut self,
+        status: StatusCode,
+        document: impl Into<PathBuf>,
+    ) -> &mut Self {
+        Arc::make_mut(&mut self.error_documents).insert(status, document.into());
+        self
+    }
+
     /// Configure a function that can rewrite requests.
     ///
     /// This function is called after parsing the request and before querying the filesystem.
@@ -174,22 +563,25 @@ impl<O: FileOpener> Resolver<O> {
     /// Certain expected IO errors are handled, though, and simply reflected in the result. These are
     /// `NotFound` and `PermissionDenied`.
     pub async fn resolve_request<B>(&self, req: &Request<B>) -> IoResult<ResolveResult<O::File>> {
+        // Parse `Accept-Encoding` header.
+        let accept_encoding = self.allowed_encodings.clone()
+            & req
+                .headers()
+                .get(header::ACCEPT_ENCODING)
+                .map(AcceptEncoding::from_header_value)
+                .unwrap_or_default();
+
         // Handle only `GET`/`HEAD` and absolute paths.
         match *req.method() {
             Method::HEAD | Method::GET => {}
             _ => {
-                return Ok(ResolveResult::MethodNotMatched);
+                let result = self
+                    .resolve_error_document(ResolveResult::MethodNotMatched, &accept_encoding)
+                    .await?;
+                return self.resolve_content_hash(result).await;
             }
         }
 
-        // Parse `Accept-Encoding` header.
-        let accept_encoding = self.allowed_encodings
-            & req
-                .headers()
-                .get(header::ACCEPT_ENCODING)
-                .map(AcceptEncoding::from_header_value)
-                .unwrap_or(AcceptEncoding::none());
-
         self.resolve_path(req.uri().path(), accept_encoding).await
     }
 
@@ -210,124 +602,493 @@ impl<O: FileOpener> Resolver<O> {
         let requested_path = RequestedPath::resolve(request_path);
 
         // Apply optional rewrite.
+        let mut params = ResolveParams {
+            path: requested_path.sanitized,
+            is_dir_request: requested_path.is_dir_request,
+            accept_encoding,
+        };
+        if let Some(ref rewrite) = self.rewrite {
+            params = rewrite(params).await?;
+        }
+
+        // Check whether the fallback applies before we give up ownership of the path.
+        let fallback = self
+            .fallback
+            .as_ref()
+            .filter(|fallback| fallback.applies_to(&params.path));
+        let accept_encoding = params.accept_encoding.clone();
+
+        let result = match self.resolve_params(params).await? {
+            ResolveResult::NotFound => match fallback {
+                Some(fallback) => self.resolve_fallback(fallback, &accept_encoding).await?,
+                None => ResolveResult::NotFound,
+            },
+            result => result,
+        };
+
+        let result = self
+            .resolve_error_document(result, &accept_encoding)
+            .await?;
+        self.resolve_content_hash(result).await
+    }
+
+    // Resolve the (possibly rewritten) request parameters.
+    async fn resolve_params(&self, params: ResolveParams) -> IoResult<ResolveResult<O::File>> {
         let ResolveParams {
-            mut path,
+            path,
             is_dir_request,
             accept_encoding,
-        } = {
-            let mut params = ResolveParams {
-                path: requested_path.sanitized,
-                is_dir_request: requested_path.is_dir_request,
-                accept_encoding,
-            };
-            if let Some(ref rewrite) = self.rewrite {
-                params = rewrite(params).await?;
-            }
-            params
-        };
+        } = params;
 
         // Try to open the file.
-        let file = match self.opener.open(&path).await {
+        let file = match self.open(&path).await {
             Ok(pair) => pair,
+            Err(err) if !is_dir_request && err.kind() == IoErrorKind::NotFound => {
+                // The exact path is missing, but a file with one of the suffixes may exist.
+                if let Some(result) = self.resolve_suffixes(&path, &accept_encoding).await? {
+                    return Ok(result);
+                }
+                if self.encoded_only {
+                    if let Some(result) = self.resolve_encoded_only(path, &accept_encoding).await? {
+                        return Ok(result);
+                    }
+                }
+                return map_open_err(err);
+            }
             Err(err) => return map_open_err(err),
         };
 
         // The resolved path doesn't contain the trailing slash anymore, so we may
-        // have opened a file for a directory request, which we treat as 'not found'.
+        // have opened a file for a directory request, which we treat as 'not found',
+        // unless configured to redirect to the path without trailing slash.
         if is_dir_request && !file.is_dir {
+            if self.strip_trailing_slash {
+                return Ok(ResolveResult::Redirect {
+                    redirect_to: redirect_target(&path, false),
+                });
+            }
             return Ok(ResolveResult::NotFound);
         }
 
-        // We may have opened a directory for a file request, in which case we redirect.
+        // We may have opened a directory for a file request, in which case we redirect, or
+        // serve the directory index in place.
         if !is_dir_request && file.is_dir {
-            // Build the redirect path. On Windows, we can't just append the entire path, because
-            // it contains Windows path separators. Instead, append each component separately.
-            let mut target = String::with_capacity(path.as_os_str().len() + 2);
-            target.push('/');
-            for component in path.components() {
-                target.push_str(&component.as_os_str().to_string_lossy());
-                target.push('/');
+            // Files with one of the suffixes take precedence over the directory.
+            if let Some(result) = self.resolve_suffixes(&path, &accept_encoding).await? {
+                return Ok(result);
             }
 
-            return Ok(ResolveResult::IsDirectory {
-                redirect_to: target,
-            });
+            if self.redirect_directories {
+                return Ok(ResolveResult::IsDirectory {
+                    redirect_to: redirect_target(&path, true),
+                });
+            }
         }
 
         // If not a directory, serve this file.
-        if !is_dir_request {
-            return self.resolve_final(file, path, accept_encoding).await;
+        if !file.is_dir {
+            if self.canonical_redirect {
+                if let Some(target) = self.canonical_target(&path).await? {
+                    return Ok(ResolveResult::Redirect {
+                        redirect_to: target,
+                    });
+                }
+            }
+            return self.resolve_final(file, path, &accept_encoding).await;
+        }
+
+        // Resolve the directory index, trying each candidate in turn.
+        for index_file in self.index_files.iter() {
+            let index_path = path.join(index_file);
+            let file = match self.open(&index_path).await {
+                Ok(pair) => pair,
+                Err(err) if err.kind() == IoErrorKind::NotFound => {
+                    if self.encoded_only {
+                        if let Some(result) = self
+                            .resolve_encoded_only(index_path, &accept_encoding)
+                            .await?
+                        {
+                            return Ok(result);
+                        }
+                    }
+                    continue;
+                }
+                Err(err) => return map_open_err(err),
+            };
+
+            // The directory index cannot itself be a directory.
+            if file.is_dir {
+                continue;
+            }
+
+            // Serve this file.
+            return self.resolve_final(file, index_path, &accept_encoding).await;
+        }
+
+        if self.autoindex {
+            return self.resolve_listing(path).await;
+        }
+
+        Ok(ResolveResult::NotFound)
+    }
+
+    // Open a file, applying the dotfile policy and the negative cache.
+    async fn open(&self, path: &Path) -> IoResult<FileWithMetadata<O::File>> {
+        let kind = match self.dotfiles {
+            DotfilePolicy::Allow => None,
+            DotfilePolicy::Deny => Some(IoErrorKind::PermissionDenied),
+            DotfilePolicy::Ignore => Some(IoErrorKind::NotFound),
+        };
+        if let Some(kind) = kind {
+            if path
+                .components()
+                .any(|component| self.is_hidden(&component.as_os_str().to_string_lossy()))
+            {
+                return Err(IoError::new(kind, "hidden path"));
+            }
+        }
+
+        let cache = match self.negative_cache {
+            Some(ref cache) => cache,
+            None => return self.opener.open(path).await,
+        };
+        if cache.is_absent(path) {
+            return Err(IoError::new(IoErrorKind::NotFound, "Not found"));
+        }
+        let res = self.opener.open(path).await;
+        if matches!(res, Err(ref err) if err.kind() == IoErrorKind::NotFound) {
+            cache.insert_absent(path);
         }
+        res
+    }
+
+    // Whether a path component is hidden according to the dotfile policy.
+    fn is_hidden(&self, name: &str) -> bool {
+        name.starts_with('.') && !self.dotfile_exemptions.iter().any(|exempt| exempt == name)
+    }
 
-        // Resolve the directory index.
-        path.push("index.html");
-        let file = match self.opener.open(&path).await {
+    // The exact path was not found as a file, try each of the suffixes in turn.
+    async fn resolve_suffixes(
+        &self,
+        path: &Path,
+        accept_encoding: &AcceptEncoding,
+    ) -> IoResult<Option<ResolveResult<O::File>>> {
+        for suffix in self.try_suffixes.iter() {
+            let mut candidate = path.as_os_str().to_owned();
+            candidate.push(suffix);
+            let candidate = PathBuf::from(candidate);
+            match self.open(&candidate).await {
+                Ok(file) if !file.is_dir => {
+                    return self
+                        .resolve_final(file, candidate, accept_encoding)
+                        .await
+                        .map(Some);
+                }
+                Ok(_) => continue,
+                Err(err) if err.kind() == IoErrorKind::NotFound => continue,
+                Err(err) => return map_open_err(err).map(Some),
+            }
+        }
+        Ok(None)
+    }
+
+    // Find the path without suffix for a file that was requested with one of the suffixes.
+    //
+    // Only returns a target if requesting it would resolve back to the same file.
+    async fn canonical_target(&self, path: &Path) -> IoResult<Option<String>> {
+        let name = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) => name,
+            None => return Ok(None),
+        };
+        let (idx, stem) = match self
+            .try_suffixes
+            .iter()
+            .enumerate()
+            .find_map(|(idx, suffix)| {
+                name.strip_suffix(suffix.as_str())
+                    .filter(|stem| !stem.is_empty())
+                    .map(|stem| (idx, stem))
+            }) {
+            Some(pair) => pair,
+            None => return Ok(None),
+        };
+
+        // The exact path must not exist as a file.
+        let canonical = path.with_file_name(stem);
+        match self.open(&canonical).await {
+            Ok(file) if !file.is_dir => return Ok(None),
+            Ok(_) => {}
+            Err(err) if err.kind() == IoErrorKind::NotFound => {}
+            Err(_) => return Ok(None),
+        }
+
+        // Suffixes tried earlier must not match a file either.
+        for suffix in &self.try_suffixes[..idx] {
+            let mut candidate = canonical.as_os_str().to_owned();
+            candidate.push(suffix);
+            match self.open(candidate.as_ref()).await {
+                Ok(file) if !file.is_dir => return Ok(None),
+                Ok(_) => {}
+                Err(err) if err.kind() == IoErrorKind::NotFound => {}
+                Err(_) => return Ok(None),
+            }
+        }
+
+        Ok(Some(redirect_target(&canonical, false)))
+    }
+
+    // Nothing was found, try the fallback document instead.
+    async fn resolve_fallback(
+        &self,
+        fallback: &Fallback,
+        accept_encoding: &AcceptEncoding,
+    ) -> IoResult<ResolveResult<O::File>> {
+        let path = sanitize_path(&fallback.document);
+        let file = match self.open(&path).await {
             Ok(pair) => pair,
             Err(err) => return map_open_err(err),
         };
 
-        // The directory index cannot itself be a directory.
+        // The fallback document cannot be a directory.
         if file.is_dir {
             return Ok(ResolveResult::NotFound);
         }
 
-        // Serve this file.
         self.resolve_final(file, path, accept_encoding).await
     }
 
+    // Replace an error result with the configured error document, if any.
+    async fn resolve_error_document(
+        &self,
+        result: ResolveResult<O::File>,
+        accept_encoding: &AcceptEncoding,
+    ) -> IoResult<ResolveResult<O::File>> {
+        let status = match result {
+            ResolveResult::MethodNotMatched => StatusCode::BAD_REQUEST,
+            ResolveResult::NotFound => StatusCode::NOT_FOUND,
+            ResolveResult::PermissionDenied => StatusCode::FORBIDDEN,
+            _ => return Ok(result),
+        };
+        let document = match self.error_documents.get(&status) {
+            Some(document) => document,
+            None => return Ok(result),
+        };
+
+        // If the error document is not accessible, fall back to the plain error.
+        let path = sanitize_path(document);
+        let file = match self.open(&path).await {
+            Ok(file) if !file.is_dir => file,
+            Ok(_) => return Ok(result),
+            Err(err) => return map_open_err::<O::File>(err).map(|_| result),
+        };
+
+        match self.resolve_final(file, path, accept_encoding).await? {
+            ResolveResult::Found(file) => Ok(ResolveResult::ErrorDocument { status, file }),
+            _ => Ok(result),
+        }
+    }
+
+    // Attach a content hash to the resolved file, if enabled and not precomputed.
+    async fn resolve_content_hash(
+        &self,
+        mut result: ResolveResult<O::File>,
+    ) -> IoResult<ResolveResult<O::File>> {
+        let file = match result {
+            ResolveResult::Found(ref mut file)
+            | ResolveResult::ErrorDocument { ref mut file, .. } => file,
+            _ => return Ok(result),
+        };
+        let algo = match self.content_hash {
+            Some(algo) if file.content_hash.is_none() && algo.is_available() => algo,
+            _ => return Ok(result),
+        };
+
+        // Files without a modification time can't be cached, so they're hashed every time.
+        let modified = match file.modified {
+            Some(modified) => modified,
+            None => {
+                file.content_hash = self
+                    .hash_file(algo, &file.path, file.size, file.modified)
+                    .await;
+                return Ok(result);
+            }
+        };
+
+        // Only one request hashes a file at a time. Others wait for it, then use the cached hash.
+        let lock = {
+            let mut cache = self.hash_cache.lock().unwrap();
+            if let Some(hash) = cache.get(&file.path, file.size, modified) {
+                file.content_hash = Some(hash);
+                return Ok(result);
+            }
+            cache.pending.entry(file.path.clone()).or_default().clone()
+        };
+        let _lock = lock.lock().await;
+        let _pending = PendingHash {
+            cache: self.hash_cache.clone(),
+            path: file.path.clone(),
+            lock: lock.clone(),
+        };
+        let cached = self
+            .hash_cache
+            .lock()
+            .unwrap()
+            .get(&file.path, file.size, modified);
+        if let Some(hash) = cached {
+            file.content_hash = Some(hash);
+            return Ok(result);
+        }
+
+        let hash = self
+            .hash_file(algo, &file.path, file.size, file.modified)
+            .await;
+        if let Some(ref hash) = hash {
+            self.hash_cache.lock().unwrap().insert(
+                file.path.clone(),
+                file.size,
+                modified,
+                hash.clone(),
+            );
+        }
+        file.content_hash = hash;
+        Ok(result)
+    }
+
+    // Hash the contents of a resolved file.
+    //
+    // The handle we have is used to serve the file, so hash a separate handle. If the file changed
+    // in the meantime, or hashing fails, the hash is skipped, and the response uses a weak entity
+    // tag.
+    async fn hash_file(
+        &self,
+        algo: ContentHash,
+        path: &Path,
+        size: u64,
+        modified: Option<SystemTime>,
+    ) -> Option<Arc<str>> {
+        let other = self.opener.open(path).await.ok()?;
+        if other.is_dir || other.size != size || other.modified != modified {
+            return None;
+        }
+        algo.hash_file(other.handle.into_file_access(), size)
+            .await
+            .ok()
+            .flatten()
+    }
+
+    // Found a directory without index, collect entries for a listing.
+    async fn resolve_listing(&self, path: PathBuf) -> IoResult<ResolveResult<O::File>> {
+        let mut entries = match self.opener.read_dir(&path).await {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == IoErrorKind::Unsupported => {
+                return Ok(ResolveResult::NotFound)
+            }
+            Err(err) => return map_open_err(err),
+        };
+        if self.dotfiles != DotfilePolicy::Allow {
+            entries.retain(|entry| !self.is_hidden(&entry.name.to_string_lossy()));
+        }
+        entries.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name)));
+        let encodings = self
+            .allowed_encodings
+            .preferred()
+            .into_iter()
+            .map(|(enc, _)| enc)
+            .collect();
+        Ok(ResolveResult::Listing(DirectoryListing {
+            path,
+            entries,
+            encodings,
+        }))
+    }
+
+    // The file is missing, try to find it in encoded form instead.
+    async fn resolve_encoded_only(
+        &self,
+        path: PathBuf,
+        accept_encoding: &AcceptEncoding,
+    ) -> IoResult<Option<ResolveResult<O::File>>> {
+        let mimetype = MimeGuess::from_path(&path)
+            .first()
+            .map(|mimetype| set_charset(mimetype).to_string());
+
+        // Prefer encodings the client accepts, which can be served as-is.
+        let accepted = accept_encoding.preferred().into_iter().map(|(enc, _)| enc);
+        let decodable = self
+            .allowed_encodings
+            .preferred()
+            .into_iter()
+            .map(|(enc, _)| enc)
+            .filter(|enc| !accept_encoding.accepts(enc) && is_available(enc));
+        for enc in accepted.chain(decodable) {
+            let mut enc_path = path.clone().into_os_string();
+            enc_path.push(enc.suffix());
+            let file = match self.open(enc_path.as_ref()).await {
+                Ok(file) if !file.is_dir => file,
+                Ok(_) => continue,
+                Err(err) if err.kind() == IoErrorKind::NotFound => continue,
+                Err(err) => return map_open_err(err).map(Some),
+            };
+
+            if accept_encoding.accepts(&enc) {
+                return Ok(Some(ResolveResult::Found(ResolvedFile::new(
+                    file,
+                    enc_path.into(),
+                    mimetype,
+                    Some(enc),
+                    true,
+                ))));
+            }
+            let mut file = ResolvedFile::new(file, enc_path.into(), mimetype, None, true);
+            file.decode = Some(enc);
+            return Ok(Some(ResolveResult::Found(file)));
+        }
+
+        Ok(None)
+    }
+
     // Found a file, perform final resolution steps.
     async fn resolve_final(
         &self,
         file: FileWithMetadata<O::File>,
         path: PathBuf,
-        accept_encoding: AcceptEncoding,
+        accept_encoding: &AcceptEncoding,
     ) -> IoResult<ResolveResult<O::File>> {
         // Determine MIME-type. This needs to happen before we resolve a pre-encoded file.
         let mimetype = MimeGuess::from_path(&path)
             .first()
             .map(|mimetype| set_charset(mimetype).to_string());
 
-        // Resolve pre-encoded files.
-        if accept_encoding.zstd {
-            let mut zstd_path = path.clone().into_os_string();
-            zstd_path.push(".zst");
-            if let Ok(file) = self.opener.open(zstd_path.as_ref()).await {
-                return Ok(ResolveResult::Found(ResolvedFile::new(
-                    file,
-                    zstd_path.into(),
-                    mimetype,
-                    Some(Encoding::Zstd),
-                )));
+        // Resolve pre-encoded files, in order of client preference. The stable sort retains server
+        // preference for encodings with equal quality values. The identity encoding only wins if
+        // the client explicitly prefers it.
+        let vary_encoding = !self.allowed_encodings.is_none();
+        let identity = accept_encoding.identity_quality().unwrap_or(0);
+        for (enc, q) in accept_encoding.preferred() {
+            if q < identity {
+                break;
             }
-        }
-        if accept_encoding.br {
-            let mut br_path = path.clone().into_os_string();
-            br_path.push(".br");
-            if let Ok(file) = self.opener.open(br_path.as_ref()).await {
-                return Ok(ResolveResult::Found(ResolvedFile::new(
-                    file,
-                    br_path.into(),
-                    mimetype,
-                    Some(Encoding::Br),
-                )));
-            }
-        }
-        if accept_encoding.gzip {
-            let mut gzip_path = path.clone().into_os_string();
-            gzip_path.push(".gz");
-            if let Ok(file) = self.opener.open(gzip_path.as_ref()).await {
+            let mut enc_path = path.clone().into_os_string();
+            enc_path.push(enc.suffix());
+            if let Ok(file) = self.open(enc_path.as_ref()).await {
                 return Ok(ResolveResult::Found(ResolvedFile::new(
                     file,
-                    gzip_path.into(),
+                    enc_path.into(),
                     mimetype,
-                    Some(Encoding::Gzip),
+                    Some(enc),
+                    vary_encoding,
                 )));
             }
         }
 
-        // No pre-encoded file found, serve the original.
+        // No pre-encoded file found, serve the original. If the client refuses the identity
+        // encoding, the response builder may still compress it, or respond with an error.
         Ok(ResolveResult::Found(ResolvedFile::new(
-            file, path, mimetype, None,
+            file,
+            path,
+            mimetype,
+            None,
+            vary_encoding,
         )))
     }
 }
@@ -336,14 +1097,45 @@ impl<O> Clone for Resolver<O> {
     fn clone(&self) -> Self {
         Self {
             opener: self.opener.clone(),
-            allowed_encodings: self.allowed_encodings,
+            allowed_encodings: self.allowed_encodings.clone(),
+            index_files: self.index_files.clone(),
+            try_suffixes: self.try_suffixes.clone(),
+            canonical_redirect: self.canonical_redirect,
+            redirect_directories: self.redirect_directories,
+            strip_trailing_slash: self.strip_trailing_slash,
+            autoindex: self.autoindex,
+            encoded_only: self.encoded_only,
+            dotfiles: self.dotfiles,
+            dotfile_exemptions: self.dotfile_exemptions.clone(),
+            fallback: self.fallback.clone(),
+            error_documents: self.error_documents.clone(),
+            content_hash: self.content_hash,
+            negative_cache: self.negative_cache.clone(),
             rewrite: self.rewrite.clone(),
+            hash_cache: self.hash_cache.clone(),
         }
     }
 }
 
 /// Type of response encoding.
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+///
+/// Besides the built-in encodings, custom encodings can be served from pre-encoded files by adding
+/// them to `Resolver::allowed_encodings`:
+///
+/// ```rust
+/// use hyper_staticfile::{AcceptEncoding, Encoding};
+///
+/// const DEFLATE: Encoding = Encoding::custom("deflate", ".zz");
+///
+/// let mut allowed = AcceptEncoding::all();
+/// allowed.insert(DEFLATE);
+///
+/// // Encodings can also be configured at runtime.
+/// let token = String::from("lz4");
+/// allowed.insert(Encoding::custom_owned(token, ".lz4"));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
 pub enum Encoding {
     /// Response body is encoded with gzip.
     Gzip,
@@ -351,78 +1143,317 @@ pub enum Encoding {
     Br,
     /// Response body is encoded with zstd.
     Zstd,
+    /// Response body is encoded with a custom encoding.
+    Custom {
+        /// The content-coding token used in HTTP headers, in lowercase.
+        token: Cow<'static, str>,
+        /// The suffix of pre-encoded files, including the leading dot.
+        suffix: Cow<'static, str>,
+    },
 }
 
 impl Encoding {
-    /// Create a `HeaderValue` for this encoding.
-    pub fn to_header_value(&self) -> HeaderValue {
-        HeaderValue::from_static(match self {
+    /// Create a custom encoding from a content-coding token and a file suffix.
+    ///
+    /// The token must be a valid HTTP token, and is best given in lowercase. The suffix should
+    /// include the leading dot.
+    pub const fn custom(token: &'static str, suffix: &'static str) -> Self {
+        Encoding::Custom {
+            token: Cow::Borrowed(token),
+            suffix: Cow::Borrowed(suffix),
+        }
+    }
+
+    /// Create a custom encoding from a content-coding token and a file suffix, which need not be
+    /// static.
+    ///
+    /// The same requirements apply as for `custom`.
+    pub fn custom_owned(token: impl Into<String>, suffix: impl Into<String>) -> Self {
+        Encoding::Custom {
+            token: Cow::Owned(token.into()),
+            suffix: Cow::Owned(suffix.into()),
+        }
+    }
+
+    /// The content-coding token used in HTTP headers.
+    pub fn token(&self) -> &str {
+        match self {
             Encoding::Gzip => "gzip",
             Encoding::Br => "br",
             Encoding::Zstd => "zstd",
-        })
+            Encoding::Custom { token, .. } => token,
+        }
+    }
+
+    /// Find the built-in encoding for a content-coding token, case-insensitively.
+    pub fn from_token(token: &str) -> Option<Self> {
+        if token.eq_ignore_ascii_case("x-gzip") {
+            return Some(Encoding::Gzip);
+        }
+        [Encoding::Gzip, Encoding::Br, Encoding::Zstd]
+            .iter()
+            .find(|enc| enc.token().eq_ignore_ascii_case(token))
+            .cloned()
+    }
+
+    /// The suffix of pre-encoded files, including the leading dot.
+    pub fn suffix(&self) -> &str {
+        match self {
+            Encoding::Gzip => ".gz",
+            Encoding::Br => ".br",
+            Encoding::Zstd => ".zst",
+            Encoding::Custom { suffix, .. } => suffix,
+        }
+    }
+
+    /// Create a `HeaderValue` for this encoding.
+    ///
+    /// Panics if the token of a custom encoding is not a valid header value.
+    pub fn to_header_value(&self) -> HeaderValue {
+        match self {
+            Encoding::Gzip => HeaderValue::from_static("gzip"),
+            Encoding::Br => HeaderValue::from_static("br"),
+            Encoding::Zstd => HeaderValue::from_static("zstd"),
+            Encoding::Custom {
+                token: Cow::Borrowed(token),
+                ..
+            } => HeaderValue::from_static(token),
+            Encoding::Custom {
+                token: Cow::Owned(token),
+                ..
+            } => HeaderValue::from_str(token).expect("invalid encoding token"),
+        }
     }
 }
 
-/// Flags for which encodings to resolve.
-#[derive(Debug, Copy, Clone)]
+/// Set of acceptable encodings, with their quality values.
+///
+/// On the resolver, this is the set of encodings the server allows, in order of preference. For a
+/// request, this is parsed from the `Accept-Encoding` header. Combining the two with `&` results in
+/// the encodings that can be served, in server preference order.
+///
+/// ```rust
+/// use hyper_staticfile::{AcceptEncoding, Encoding};
+///
+/// // Allow only brotli and gzip, preferring brotli when the client has no preference.
+/// let allowed: AcceptEncoding = vec![Encoding::Br, Encoding::Gzip].into_iter().collect();
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct AcceptEncoding {
-    /// Look for `.gz` files.
-    pub gzip: bool,
-    /// Look for `.br` files.
-    pub br: bool,
-    /// Look for `.zst` files.
-    pub zstd: bool,
+    /// Explicitly listed encodings, with quality values in thousandths.
+    encodings: Cow<'static, [(Encoding, u16)]>,
+    /// Unrecognized tokens from a header value, which may match custom encodings.
+    others: Vec<(String, u16)>,
+    /// Quality value for unlisted encodings, from a `*` entry.
+    wildcard: Option<u16>,
+    /// Quality value for the identity encoding, if listed.
+    identity: Option<u16>,
 }
 
 impl AcceptEncoding {
-    /// Return an `AcceptEncoding` with all flags set.
+    /// Return an `AcceptEncoding` with all supported encodings, preferring zstd, then brotli,
+    /// then gzip.
     pub const fn all() -> Self {
+        const ALL: &[(Encoding, u16)] = &[
+            (Encoding::Zstd, 1000),
+            (Encoding::Br, 1000),
+            (Encoding::Gzip, 1000),
+        ];
         Self {
-            gzip: true,
-            br: true,
-            zstd: true,
+            encodings: Cow::Borrowed(ALL),
+            others: Vec::new(),
+            wildcard: None,
+            identity: None,
         }
     }
 
-    /// Return an `AcceptEncoding` with no flags set.
+    /// Return an `AcceptEncoding` that allows only the identity encoding.
     pub const fn none() -> Self {
         Self {
-            gzip: false,
-            br: false,
-            zstd: false,
+            encodings: Cow::Borrowed(&[]),
+            others: Vec::new(),
+            wildcard: None,
+            identity: None,
+        }
+    }
+
+    /// Add an encoding with a quality value of 1, if not already present.
+    ///
+    /// On the resolver, this gives the encoding the lowest server preference. This is how custom
+    /// encodings are registered, see `Encoding::custom`.
+    pub fn insert(&mut self, enc: Encoding) -> &mut Self {
+        if !self.encodings.iter().any(|(other, _)| *other == enc) {
+            self.encodings.to_mut().push((enc, 1000));
         }
+        self
+    }
+
+    /// Whether no encodings other than identity are acceptable, like `AcceptEncoding::none()`.
+    pub fn is_none(&self) -> bool {
+        self.wildcard.unwrap_or(0) == 0
+            && self.encodings.iter().all(|(_, q)| *q == 0)
+            && self.others.iter().all(|(_, q)| *q == 0)
     }
 
     /// Fill an `AcceptEncoding` struct from a header value.
+    ///
+    /// Entries with a quality value of zero are rejected, and a `*` entry applies to all
+    /// encodings not listed explicitly. The identity encoding is acceptable unless rejected with
+    /// `identity;q=0`, or with `*;q=0` without a separate `identity` entry.
     pub fn from_header_value(value: &HeaderValue) -> Self {
         let mut res = Self::none();
+        let mut identity = None;
         if let Ok(value) = value.to_str() {
-            for enc in value.split(',') {
-                // TODO: Handle weights (q=)
-                match enc.split(';').next().unwrap().trim() {
-                    "gzip" => res.gzip = true,
-                    "br" => res.br = true,
-                    "zstd" => res.zstd = true,
-                    _ => {}
+            for item in value.split(',') {
+                let mut parts = item.split(';');
+                let token = parts.next().unwrap().trim();
+                let mut q = Some(1000);
+                for param in parts {
+                    if let Some((key, value)) = param.split_once('=') {
+                        if key.trim().eq_ignore_ascii_case("q") {
+                            q = parse_qvalue(value.trim());
+                        }
+                    }
+                }
+                let q = match q {
+                    Some(q) => q,
+                    None => continue,
+                };
+
+                if token == "*" {
+                    res.wildcard = Some(q);
+                } else if token.eq_ignore_ascii_case("identity") {
+                    identity = Some(q);
+                } else if let Some(enc) = Encoding::from_token(token) {
+                    if !res.encodings.iter().any(|(other, _)| *other == enc) {
+                        res.encodings.to_mut().push((enc, q));
+                    }
+                } else {
+                    let token = token.to_ascii_lowercase();
+                    if !res.others.iter().any(|(other, _)| *other == token) {
+                        res.others.push((token, q));
+                    }
                 }
             }
         }
+        res.identity = identity.or(res.wildcard);
+        res
+    }
+
+    /// The quality value of an encoding, in thousandths. Zero means not acceptable.
+    pub fn quality(&self, enc: &Encoding) -> u16 {
+        self.encodings
+            .iter()
+            .find(|(other, _)| other == enc)
+            .map(|(_, q)| *q)
+            .or_else(|| match enc {
+                Encoding::Custom { token, .. } => self
+                    .others
+                    .iter()
+                    .find(|(other, _)| other.eq_ignore_ascii_case(token))
+                    .map(|(_, q)| *q),
+                _ => None,
+            })
+            .or(self.wildcard)
+            .unwrap_or(0)
+    }
+
+    /// The quality value of the identity encoding, in thousandths. Zero means not acceptable.
+    ///
+    /// Returns `None` if the identity encoding was not listed, in which case it is acceptable, but
+    /// less preferred than any other acceptable encoding.
+    pub fn identity_quality(&self) -> Option<u16> {
+        self.identity
+    }
+
+    /// Whether the identity encoding is acceptable.
+    pub fn accepts_identity(&self) -> bool {
+        self.identity != Some(0)
+    }
+
+    /// Whether an encoding is acceptable.
+    pub fn accepts(&self, enc: &Encoding) -> bool {
+        self.quality(enc) > 0
+    }
+
+    /// Acceptable encodings, ordered by descending quality value.
+    ///
+    /// Encodings with equal quality values retain their original order. Encodings only covered by
+    /// a `*` entry are not included.
+    pub fn preferred(&self) -> Vec<(Encoding, u16)> {
+        let mut res: Vec<_> = self
+            .encodings
+            .iter()
+            .filter(|(_, q)| *q > 0)
+            .cloned()
+            .collect();
+        res.sort_by_key(|(_, q)| std::cmp::Reverse(*q));
+        res
+    }
+}
+
+impl Default for AcceptEncoding {
+    fn default() -> Self {
+        Self::none()
+    }
+}
+
+impl FromIterator<Encoding> for AcceptEncoding {
+    /// Collect encodings in order of preference, all with a quality value of 1.
+    fn from_iter<I: IntoIterator<Item = Encoding>>(iter: I) -> Self {
+        let mut res = Self::none();
+        for enc in iter {
+            res.insert(enc);
+        }
         res
     }
 }
 
 impl BitAnd for AcceptEncoding {
     type Output = Self;
+
+    /// Intersect two sets, taking the lowest quality value for each encoding.
+    ///
+    /// The order of the left-hand side is retained, followed by any additional encodings listed
+    /// on the right-hand side.
     fn bitand(self, rhs: Self) -> Self {
+        let mut encodings = Vec::new();
+        for (enc, _) in self.encodings.iter().chain(rhs.encodings.iter()) {
+            if encodings.iter().any(|(other, _)| other == enc) {
+                continue;
+            }
+            let q = self.quality(enc).min(rhs.quality(enc));
+            if q > 0 {
+                encodings.push((enc.clone(), q));
+            }
+        }
         Self {
-            gzip: self.gzip && rhs.gzip,
-            br: self.br && rhs.br,
-            zstd: self.zstd && rhs.zstd,
+            encodings: encodings.into(),
+            others: Vec::new(),
+            wildcard: self.wildcard.zip(rhs.wildcard).map(|(a, b)| a.min(b)),
+            identity: match (self.identity, rhs.identity) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            },
         }
     }
 }
 
+/// Parse an HTTP quality value into thousandths.
+fn parse_qvalue(value: &str) -> Option<u16> {
+    let (int, frac) = value.split_once('.').unwrap_or((value, ""));
+    if frac.len() > 3 || !frac.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let frac = frac.bytes().chain(std::iter::repeat(b'0')).take(3);
+    let frac = frac.fold(0, |acc, b| acc * 10 + u16::from(b - b'0'));
+    match int {
+        "0" => Some(frac),
+        "1" if frac == 0 => Some(1000),
+        _ => None,
+    }
+}
+
 fn set_charset(mimetype: Mime) -> Mime {
     if mimetype == mime::APPLICATION_JAVASCRIPT {
         return mime::APPLICATION_JAVASCRIPT_UTF_8;
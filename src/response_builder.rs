@@ -1,9 +1,52 @@
+use std::sync::Arc;
+
 use http::{
-    header, response::Builder as HttpResponseBuilder, HeaderMap, Method, Request, Response, Result,
-    StatusCode, Uri,
+    header, response::Builder as HttpResponseBuilder, HeaderMap, HeaderValue, Method, Request,
+    Response, Result, StatusCode, Uri,
+};
+
+use crate::{
+    resolve::{DirectoryListing, ResolveResult},
+    util::{directory_url_path, render_html_listing, render_json_listing, FileResponseBuilder},
+    vfs::IntoFileAccess,
+    Body, CachePolicy, Compression, ValidatorProvider,
 };
 
-use crate::{resolve::ResolveResult, util::FileResponseBuilder, vfs::IntoFileAccess, Body};
+/// Format of generated directory listings.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ListingFormat {
+    /// An HTML document, for browsers.
+    #[default]
+    Html,
+    /// A JSON array of entries, for tooling.
+    Json,
+}
+
+/// Status code used for redirects, such as for directories requested without trailing slash.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RedirectStatus {
+    /// `301 Moved Permanently`
+    #[default]
+    MovedPermanently,
+    /// `302 Found`
+    Found,
+    /// `307 Temporary Redirect`
+    TemporaryRedirect,
+    /// `308 Permanent Redirect`
+    PermanentRedirect,
+}
+
+impl RedirectStatus {
+    /// Get the HTTP status code.
+    pub fn to_status_code(&self) -> StatusCode {
+        match self {
+            RedirectStatus::MovedPermanently => StatusCode::MOVED_PERMANENTLY,
+            RedirectStatus::Found => StatusCode::FOUND,
+            RedirectStatus::TemporaryRedirect => StatusCode::TEMPORARY_REDIRECT,
+            RedirectStatus::PermanentRedirect => StatusCode::PERMANENT_REDIRECT,
+        }
+    }
+}
 
 /// Utility to build the default response for a `resolve` result.
 ///
@@ -16,6 +59,12 @@ pub struct ResponseBuilder<'a> {
     pub path: &'a str,
     /// The request query string.
     pub query: Option<&'a str>,
+    /// Status code used for redirects.
+    pub redirect_status: RedirectStatus,
+    /// Format of directory listings, typically negotiated using the `Accept` header.
+    pub listing_format: ListingFormat,
+    /// Maximum number of entries per page in JSON directory listings.
+    pub listing_page_size: Option<usize>,
     /// Inner file response builder.
     pub file_response_builder: FileResponseBuilder,
 }
@@ -40,6 +89,7 @@ impl<'a> ResponseBuilder<'a> {
         headers: &'a HeaderMap,
     ) -> &mut Self {
         self.request_uri(uri);
+        self.accept_header(headers.get(header::ACCEPT));
         self.file_response_builder.request_parts(method, headers);
         self
     }
@@ -57,6 +107,59 @@ impl<'a> ResponseBuilder<'a> {
         self
     }
 
+    /// Add cache headers to responses according to the given rules.
+    pub fn cache_control(&mut self, value: Option<Arc<CachePolicy>>) -> &mut Self {
+        self.file_response_builder.cache_control(value);
+        self
+    }
+
+    /// Compress responses on the fly using the given settings.
+    pub fn compression(&mut self, value: Option<Arc<Compression>>) -> &mut Self {
+        self.file_response_builder.compression(value);
+        self
+    }
+
+    /// Determine `ETag` and `Last-Modified` values using the given provider.
+    pub fn validators(&mut self, value: Option<Arc<dyn ValidatorProvider>>) -> &mut Self {
+        self.file_response_builder.validators(value);
+        self
+    }
+
+    /// Set the status code used for redirects.
+    pub fn redirect_status(&mut self, value: RedirectStatus) -> &mut Self {
+        self.redirect_status = value;
+        self
+    }
+
+    /// Negotiate the directory listing format using the given `Accept` request header value.
+    ///
+    /// JSON is chosen if the client accepts `application/json` at least as much as `text/html`.
+    pub fn accept_header(&mut self, value: Option<&HeaderValue>) -> &mut Self {
+        self.listing_format = match value.and_then(|v| v.to_str().ok()) {
+            Some(value) if prefers_json(value) => ListingFormat::Json,
+            _ => ListingFormat::Html,
+        };
+        self
+    }
+
+    /// Set the format of directory listings.
+    pub fn listing_format(&mut self, value: ListingFormat) -> &mut Self {
+        self.listing_format = value;
+        self
+    }
+
+    /// Set the maximum number of entries per page in JSON directory listings.
+    ///
+    /// Pages are requested using the `cursor` query parameter. If there are more entries, the
+    /// response has a `Link` header with `rel="next"` pointing to the next page.
+    ///
+    /// The cursor is an offset into the sorted listing, so if entries are added or removed between
+    /// requests, a page may skip or repeat entries.
+    pub fn listing_page_size(&mut self, value: Option<usize>) -> &mut Self {
+        self.listing_page_size = value;
+        self
+    }
+
     /// Set the request path.
     pub fn path(&mut self, value: &'a str) -> &mut Self {
         self.path = value;
@@ -89,6 +192,9 @@ impl<'a> ResponseBuilder<'a> {
                 .body(Body::Empty),
             ResolveResult::IsDirectory {
                 redirect_to: mut target,
+            }
+            | ResolveResult::Redirect {
+                redirect_to: mut target,
             } => {
                 // Preserve any query string from the original request.
                 if let Some(query) = self.query {
@@ -97,11 +203,94 @@ impl<'a> ResponseBuilder<'a> {
                 }
 
                 HttpResponseBuilder::new()
-                    .status(StatusCode::MOVED_PERMANENTLY)
+                    .status(self.redirect_status.to_status_code())
                     .header(header::LOCATION, target)
                     .body(Body::Empty)
             }
             ResolveResult::Found(file) => self.file_response_builder.build(file),
+            ResolveResult::ErrorDocument { status, file } => {
+                // Serve the document as-is, ignoring conditional and range headers.
+                let mut res = FileResponseBuilder::new()
+                    .is_head(self.file_response_builder.is_head)
+                    .build(file)?;
+                *res.status_mut() = status;
+
+                // Validators of the document don't apply to the error response.
+                let headers = res.headers_mut();
+                headers.remove(header::ETAG);
+                headers.remove(header::LAST_MODIFIED);
+                headers.remove(header::ACCEPT_RANGES);
+                Ok(res)
+            }
+            ResolveResult::Listing(listing) => self.build_listing(listing),
+        }
+    }
+
+    // Build a response for a directory listing.
+    fn build_listing<F>(&self, listing: DirectoryListing) -> Result<Response<Body<F>>> {
+        let mut res = HttpResponseBuilder::new()
+            .status(StatusCode::OK)
+            .header(header::VARY, "Accept");
+
+        let body = match self.listing_format {
+            ListingFormat::Html => {
+                res = res.header(header::CONTENT_TYPE, "text/html; charset=utf-8");
+                render_html_listing(&listing)
+            }
+            ListingFormat::Json => {
+                let total = listing.entries.len();
+                let start = self
+                    .query
+                    .and_then(|query| {
+                        url::form_urlencoded::parse(query.as_bytes())
+                            .find(|(key, _)| key == "cursor")
+                            .and_then(|(_, value)| value.parse().ok())
+                    })
+                    .unwrap_or(0)
+                    .min(total);
+                let end = match self.listing_page_size {
+                    Some(size) => start.saturating_add(size.max(1)).min(total),
+                    None => total,
+                };
+
+                if end < total {
+                    let next = directory_url_path(&listing.path, true);
+                    res = res.header(
+                        header::LINK,
+                        format!("<{}?cursor={}>; rel=\"next\"", next, end),
+                    );
+                }
+
+                res = res.header(header::CONTENT_TYPE, "application/json");
+                render_json_listing(&listing, start..end)
+            }
+        };
+
+        res = res.header(header::CONTENT_LENGTH, format!("{}", body.len()));
+        if self.file_response_builder.is_head {
+            res.body(Body::Empty)
+        } else {
+            res.body(Body::Bytes(body.into()))
+        }
+    }
+}
+
+/// Whether an `Accept` header value prefers JSON over HTML.
+fn prefers_json(value: &str) -> bool {
+    let mut json_q = 0.0;
+    let mut html_q = 0.0;
+    for item in value.split(',') {
+        let mut parts = item.split(';');
+        let media_range = parts.next().unwrap().trim();
+        let q = parts
+            .filter_map(|param| param.trim().strip_prefix("q="))
+            .find_map(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+        match media_range {
+            "application/json" => json_q = q,
+            "text/html" | "text/*" | "*/*" if q > html_q => html_q = q,
+            _ => {}
         }
     }
+    json_q > 0.0 && json_q >= html_q
 }
@@ -1,11 +1,12 @@
-use std::{future::Future, io::Error as IoError, path::PathBuf, pin::Pin};
+use std::{future::Future, io::Error as IoError, path::PathBuf, pin::Pin, sync::Arc};
 
-use http::{Request, Response};
+use http::{Request, Response, StatusCode};
 use hyper::service::Service;
 
 use crate::{
     vfs::{FileOpener, IntoFileAccess, TokioFileOpener},
-    AcceptEncoding, Body, Resolver, ResponseBuilder,
+    AcceptEncoding, Body, CachePolicy, Compression, ContentHash, DotfilePolicy, Fallback,
+    NegativeCache, RedirectStatus, Resolver, ResponseBuilder, ValidatorProvider,
 };
 
 /// High-level interface for serving static files.
@@ -25,8 +26,16 @@ use crate::{
 pub struct Static<O = TokioFileOpener> {
     /// The resolver instance used to open files.
     pub resolver: Resolver<O>,
-    /// Whether to send cache headers, and what lifespan to indicate.
-    pub cache_headers: Option<u32>,
+    /// Rules for the `Cache-Control` header, if any.
+    pub cache_control: Option<Arc<CachePolicy>>,
+    /// Status code used for redirects.
+    pub redirect_status: RedirectStatus,
+    /// Maximum number of entries per page in JSON directory listings.
+    pub listing_page_size: Option<usize>,
+    /// Settings for compressing responses on the fly, if enabled.
+    pub compression: Option<Arc<Compression>>,
+    /// Provider of the `ETag` and `Last-Modified` values, if not `DefaultValidators`.
+    pub validators: Option<Arc<dyn ValidatorProvider>>,
 }
 
 impl Static<TokioFileOpener> {
@@ -37,7 +46,11 @@ impl Static<TokioFileOpener> {
     pub fn new(root: impl Into<PathBuf>) -> Self {
         Self {
             resolver: Resolver::new(root),
-            cache_headers: None,
+            cache_control: None,
+            redirect_status: RedirectStatus::default(),
+            listing_page_size: None,
+            compression: None,
+            validators: None,
         }
     }
 }
@@ -47,13 +60,47 @@ impl<O: FileOpener> Static<O> {
     pub fn with_opener(opener: O) -> Self {
         Self {
             resolver: Resolver::with_opener(opener),
-            cache_headers: None,
+            cache_control: None,
+            redirect_status: RedirectStatus::default(),
+            listing_page_size: None,
+            compression: None,
+            validators: None,
         }
     }
 
     /// Add cache headers to responses for the given lifespan.
+    ///
+    /// This is a shorthand for `cache_control` with `CachePolicy::max_age`.
     pub fn cache_headers(&mut self, value: Option<u32>) -> &mut Self {
-        self.cache_headers = value;
+        self.cache_control(value.map(CachePolicy::max_age))
+    }
+
+    /// Add cache headers to responses according to the given rules.
+    ///
+    /// See `CachePolicy` for details.
+    pub fn cache_control(&mut self, policy: Option<CachePolicy>) -> &mut Self {
+        self.cache_control = policy.map(Arc::new);
+        self
+    }
+
+    /// Set the status code used for redirects.
+    pub fn redirect_status(&mut self, value: RedirectStatus) -> &mut Self {
+        self.redirect_status = value;
+        self
+    }
+
+    /// Enable or disable redirecting requests for a directory without trailing slash.
+    ///
+    /// When disabled, the directory index is served in place.
+    pub fn redirect_directories(&mut self, value: bool) -> &mut Self {
+        self.resolver.redirect_directories = value;
+        self
+    }
+
+    /// Enable or disable redirecting requests for a file with trailing slash to the path
+    /// without it.
+    pub fn strip_trailing_slash(&mut self, value: bool) -> &mut Self {
+        self.resolver.strip_trailing_slash = value;
         self
     }
 
@@ -63,6 +110,111 @@ impl<O: FileOpener> Static<O> {
         self
     }
 
+    /// Set the file names to try, in order, when a directory is requested.
+    pub fn index_files<I, S>(&mut self, files: I) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.resolver.set_index_files(files);
+        self
+    }
+
+    /// Set file name suffixes to try, in order, when the exact path is not found.
+    pub fn try_suffixes<I, S>(&mut self, suffixes: I) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.resolver.set_try_suffixes(suffixes);
+        self
+    }
+
+    /// Enable or disable redirecting requests that include one of the `try_suffixes` to the path
+    /// without it.
+    pub fn canonical_redirect(&mut self, value: bool) -> &mut Self {
+        self.resolver.canonical_redirect = value;
+        self
+    }
+
+    /// Enable or disable listing the contents of directories that have no index file.
+    pub fn autoindex(&mut self, value: bool) -> &mut Self {
+        self.resolver.autoindex = value;
+        self
+    }
+
+    /// Enable or disable serving files that only exist in encoded form, such as `app.js.gz`.
+    ///
+    /// See `Resolver::encoded_only` for details.
+    pub fn encoded_only(&mut self, value: bool) -> &mut Self {
+        self.resolver.encoded_only = value;
+        self
+    }
+
+    /// Set the algorithm to hash file contents with, for strong entity tags.
+    ///
+    /// See `Resolver::content_hash` for details.
+    pub fn content_hash(&mut self, algo: Option<ContentHash>) -> &mut Self {
+        self.resolver.content_hash = algo;
+        self
+    }
+
+    /// Set a cache of paths found to be absent, to avoid repeated lookups.
+    ///
+    /// See `NegativeCache` for details.
+    pub fn negative_cache(&mut self, cache: Option<Arc<NegativeCache>>) -> &mut Self {
+        self.resolver.negative_cache = cache;
+        self
+    }
+
+    /// Set how to treat path components that start with a dot, such as `.git` or `.env`.
+    pub fn dotfiles(&mut self, policy: DotfilePolicy) -> &mut Self {
+        self.resolver.dotfiles = policy;
+        self
+    }
+
+    /// Set a document to serve when a file is not found, such as for single-page applications.
+    pub fn fallback(&mut self, fallback: Option<Fallback>) -> &mut Self {
+        self.resolver.set_fallback(fallback);
+        self
+    }
+
+    /// Set a document to serve for error responses with the given status code.
+    ///
+    /// Supported are `400 Bad Request`, `403 Forbidden` and `404 Not Found`.
+    pub fn error_document(
+        &mut self,
+        status: StatusCode,
+        document: impl Into<PathBuf>,
+    ) -> &mut Self {
+        self.resolver.set_error_document(status, document);
+        self
+    }
+
+    /// Set the maximum number of entries per page in JSON directory listings.
+    ///
+    /// See `ResponseBuilder::listing_page_size`.
+    pub fn listing_page_size(&mut self, value: Option<usize>) -> &mut Self {
+        self.listing_page_size = value;
+        self
+    }
+
+    /// Enable or disable compressing responses on the fly.
+    ///
+    /// This only applies to files without a pre-encoded variant. See `Compression` for details.
+    pub fn compression(&mut self, compression: Option<Compression>) -> &mut Self {
+        self.compression = compression.map(Arc::new);
+        self
+    }
+
+    /// Set the provider of `ETag` and `Last-Modified` values.
+    ///
+    /// See `ValidatorProvider` for details.
+    pub fn validators(&mut self, provider: impl ValidatorProvider + 'static) -> &mut Self {
+        self.validators = Some(Arc::new(provider));
+        self
+    }
+
     /// Serve a request.
     pub async fn serve<B>(
         self,
@@ -70,12 +222,20 @@ impl<O: FileOpener> Static<O> {
     ) -> Result<Response<Body<<O::File as IntoFileAccess>::Output>>, IoError> {
         let Self {
             resolver,
-            cache_headers,
+            cache_control,
+            redirect_status,
+            listing_page_size,
+            compression,
+            validators,
         } = self;
         resolver.resolve_request(&request).await.map(|result| {
             ResponseBuilder::new()
                 .request(&request)
-                .cache_headers(cache_headers)
+                .cache_control(cache_control)
+                .redirect_status(redirect_status)
+                .listing_page_size(listing_page_size)
+                .compression(compression)
+                .validators(validators)
                 .build(result)
                 .expect("unable to build response")
         })
@@ -86,7 +246,11 @@ impl<O> Clone for Static<O> {
     fn clone(&self) -> Self {
         Self {
             resolver: self.resolver.clone(),
-            cache_headers: self.cache_headers,
+            cache_control: self.cache_control.clone(),
+            redirect_status: self.redirect_status,
+            listing_page_size: self.listing_page_size,
+            compression: self.compression.clone(),
+            validators: self.validators.clone(),
         }
     }
 }
@@ -0,0 +1,290 @@
+// Without any encoding features, `Encoder` and `Decoder` are uninhabited.
+#![cfg_attr(
+    not(any(feature = "gzip", feature = "brotli", feature = "zstd")),
+    allow(unreachable_code, unused_variables)
+)]
+
+use std::{
+    io::{Error as IoError, ErrorKind},
+    mem,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+#[cfg(any(feature = "gzip", feature = "brotli", feature = "zstd"))]
+use std::io::Write;
+
+use futures_util::stream::Stream;
+use hyper::body::Bytes;
+
+use crate::{
+    util::FileBytesStream,
+    vfs::{FileAccess, TokioFileAccess},
+    CompressionLevel, Encoding,
+};
+
+/// Wraps a `FileBytesStream` and implements a stream of compressed `Bytes`s.
+pub struct CompressedStream<F = TokioFileAccess> {
+    inner: FileBytesStream<F>,
+    encoder: Option<Encoder>,
+}
+
+impl<F> CompressedStream<F> {
+    /// Create a new stream that compresses the given file stream.
+    ///
+    /// Returns an error of kind `ErrorKind::Unsupported` if support for the encoding was not
+    /// enabled using its crate feature.
+    pub fn new(
+        inner: FileBytesStream<F>,
+        encoding: Encoding,
+        level: CompressionLevel,
+    ) -> Result<Self, IoError> {
+        Ok(Self {
+            inner,
+            encoder: Some(Encoder::new(&encoding, level.for_encoding(&encoding))?),
+        })
+    }
+}
+
+impl<F: FileAccess> Stream for CompressedStream<F> {
+    type Item = Result<Bytes, IoError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let Self {
+            ref mut inner,
+            ref mut encoder,
+        } = *self;
+        poll_coder(inner, encoder, cx)
+    }
+}
+
+/// Compress a buffer in memory.
+///
+/// This is a blocking operation, so should be run using `spawn_blocking`.
+pub(crate) fn compress_slice(
+    data: &[u8],
+    encoding: Encoding,
+    level: CompressionLevel,
+) -> Result<Bytes, IoError> {
+    let mut encoder = Encoder::new(&encoding, level.for_encoding(&encoding))?;
+    let mut out = Vec::from(encoder.write(data)?);
+    out.extend_from_slice(&encoder.finish()?);
+    Ok(out.into())
+}
+
+/// Wraps a `FileBytesStream` of an encoded file and implements a stream of decompressed `Bytes`s.
+pub struct DecompressedStream<F = TokioFileAccess> {
+    inner: FileBytesStream<F>,
+    decoder: Option<Decoder>,
+}
+
+impl<F> DecompressedStream<F> {
+    /// Create a new stream that decompresses the given file stream.
+    ///
+    /// Returns an error of kind `ErrorKind::Unsupported` if support for the encoding was not
+    /// enabled using its crate feature.
+    pub fn new(inner: FileBytesStream<F>, encoding: Encoding) -> Result<Self, IoError> {
+        Ok(Self {
+            inner,
+            decoder: Some(Decoder::new(&encoding)?),
+        })
+    }
+}
+
+impl<F: FileAccess> Stream for DecompressedStream<F> {
+    type Item = Result<Bytes, IoError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let Self {
+            ref mut inner,
+            ref mut decoder,
+        } = *self;
+        poll_coder(inner, decoder, cx)
+    }
+}
+
+/// Common interface of `Encoder` and `Decoder`.
+trait Coder: Sized {
+    /// Process a chunk, and return any output that is ready.
+    fn write(&mut self, buf: &[u8]) -> Result<Bytes, IoError>;
+
+    /// Finish the stream, and return the remaining output.
+    fn finish(self) -> Result<Bytes, IoError>;
+}
+
+/// Poll the file stream, and pass its data through the coder.
+fn poll_coder<F: FileAccess, C: Coder>(
+    inner: &mut FileBytesStream<F>,
+    coder: &mut Option<C>,
+    cx: &mut Context,
+) -> Poll<Option<Result<Bytes, IoError>>> {
+    loop {
+        let state = match coder {
+            Some(state) => state,
+            None => return Poll::Ready(None),
+        };
+
+        // Coders buffer internally, so keep reading until there is output.
+        let res = match ready!(Pin::new(&mut *inner).poll_next(cx)) {
+            Some(Ok(buf)) => state.write(&buf),
+            Some(Err(err)) => Err(err),
+            None => coder.take().unwrap().finish(),
+        };
+        match res {
+            Ok(buf) if buf.is_empty() => continue,
+            Ok(buf) => return Poll::Ready(Some(Ok(buf))),
+            Err(err) => {
+                *coder = None;
+                return Poll::Ready(Some(Err(err)));
+            }
+        }
+    }
+}
+
+/// Streaming encoder for one of the supported encodings, writing to a buffer.
+enum Encoder {
+    #[cfg(feature = "gzip")]
+    Gzip(flate2::write::GzEncoder<Vec<u8>>),
+    #[cfg(feature = "brotli")]
+    Br(Box<brotli::CompressorWriter<Vec<u8>>>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::stream::write::Encoder<'static, Vec<u8>>),
+}
+
+impl Encoder {
+    fn new(encoding: &Encoding, level: i32) -> Result<Self, IoError> {
+        match encoding {
+            #[cfg(feature = "gzip")]
+            Encoding::Gzip => Ok(Encoder::Gzip(flate2::write::GzEncoder::new(
+                Vec::new(),
+                flate2::Compression::new(level as u32),
+            ))),
+            #[cfg(feature = "brotli")]
+            Encoding::Br => Ok(Encoder::Br(Box::new(brotli::CompressorWriter::new(
+                Vec::new(),
+                4096,
+                level as u32,
+                22,
+            )))),
+            #[cfg(feature = "zstd")]
+            Encoding::Zstd => Ok(Encoder::Zstd(zstd::stream::write::Encoder::new(
+                Vec::new(),
+                level,
+            )?)),
+            #[allow(unreachable_patterns)]
+            _ => Err(IoError::new(
+                ErrorKind::Unsupported,
+                "encoding support not enabled",
+            )),
+        }
+    }
+}
+
+impl Coder for Encoder {
+    fn write(&mut self, buf: &[u8]) -> Result<Bytes, IoError> {
+        let out: &mut Vec<u8> = match *self {
+            #[cfg(feature = "gzip")]
+            Encoder::Gzip(ref mut enc) => {
+                enc.write_all(buf)?;
+                enc.get_mut()
+            }
+            #[cfg(feature = "brotli")]
+            Encoder::Br(ref mut enc) => {
+                enc.write_all(buf)?;
+                enc.get_mut()
+            }
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(ref mut enc) => {
+                enc.write_all(buf)?;
+                enc.get_mut()
+            }
+        };
+        Ok(mem::take(out).into())
+    }
+
+    fn finish(self) -> Result<Bytes, IoError> {
+        let out: Vec<u8> = match self {
+            #[cfg(feature = "gzip")]
+            Encoder::Gzip(enc) => enc.finish()?,
+            #[cfg(feature = "brotli")]
+            Encoder::Br(enc) => enc.into_inner(),
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(enc) => enc.finish()?,
+        };
+        Ok(out.into())
+    }
+}
+
+/// Streaming decoder for one of the supported encodings, writing to a buffer.
+enum Decoder {
+    #[cfg(feature = "gzip")]
+    Gzip(flate2::write::GzDecoder<Vec<u8>>),
+    #[cfg(feature = "brotli")]
+    Br(Box<brotli::DecompressorWriter<Vec<u8>>>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::stream::write::Decoder<'static, Vec<u8>>),
+}
+
+impl Decoder {
+    fn new(encoding: &Encoding) -> Result<Self, IoError> {
+        match encoding {
+            #[cfg(feature = "gzip")]
+            Encoding::Gzip => Ok(Decoder::Gzip(flate2::write::GzDecoder::new(Vec::new()))),
+            #[cfg(feature = "brotli")]
+            Encoding::Br => Ok(Decoder::Br(Box::new(brotli::DecompressorWriter::new(
+                Vec::new(),
+                4096,
+            )))),
+            #[cfg(feature = "zstd")]
+            Encoding::Zstd => Ok(Decoder::Zstd(
+                zstd::stream::write::Decoder::new(Vec::new())?,
+            )),
+            #[allow(unreachable_patterns)]
+            _ => Err(IoError::new(
+                ErrorKind::Unsupported,
+                "encoding support not enabled",
+            )),
+        }
+    }
+}
+
+impl Coder for Decoder {
+    fn write(&mut self, buf: &[u8]) -> Result<Bytes, IoError> {
+        let out: &mut Vec<u8> = match *self {
+            #[cfg(feature = "gzip")]
+            Decoder::Gzip(ref mut dec) => {
+                dec.write_all(buf)?;
+                dec.get_mut()
+            }
+            #[cfg(feature = "brotli")]
+            Decoder::Br(ref mut dec) => {
+                dec.write_all(buf)?;
+                dec.get_mut()
+            }
+            #[cfg(feature = "zstd")]
+            Decoder::Zstd(ref mut dec) => {
+                dec.write_all(buf)?;
+                dec.get_mut()
+            }
+        };
+        Ok(mem::take(out).into())
+    }
+
+    fn finish(self) -> Result<Bytes, IoError> {
+        let out: Vec<u8> = match self {
+            #[cfg(feature = "gzip")]
+            Decoder::Gzip(dec) => dec.finish()?,
+            #[cfg(feature = "brotli")]
+            Decoder::Br(mut dec) => {
+                dec.close()?;
+                dec.into_inner().unwrap_or_else(|out| out)
+            }
+            #[cfg(feature = "zstd")]
+            Decoder::Zstd(mut dec) => {
+                dec.flush()?;
+                dec.into_inner()
+            }
+        };
+        Ok(out.into())
+    }
+}
@@ -0,0 +1,169 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Outcome of evaluating the preconditions of a request.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum Precondition {
+    /// Perform the request. Contains whether a `Range` header should be honored.
+    Proceed { range: bool },
+    /// Respond with `304 Not Modified`.
+    NotModified,
+    /// Respond with `412 Precondition Failed`.
+    Failed,
+}
+
+/// Validators of the selected representation, and the conditional headers of the request.
+pub(crate) struct Conditions<'a> {
+    pub etag: Option<&'a str>,
+    pub modified: Option<SystemTime>,
+    pub if_match: Option<&'a str>,
+    pub if_none_match: Option<&'a str>,
+    pub if_modified_since: Option<SystemTime>,
+    pub if_unmodified_since: Option<SystemTime>,
+    pub if_range: Option<&'a str>,
+}
+
+impl Conditions<'_> {
+    /// Evaluate preconditions in the order of RFC 9110, section 13.2.2.
+    ///
+    /// This assumes a `GET` or `HEAD` request.
+    pub fn evaluate(&self) -> Precondition {
+        if let Some(if_match) = self.if_match {
+            if !etag_list_matches(if_match, self.etag, true) {
+                return Precondition::Failed;
+            }
+        } else if let (Some(modified), Some(since)) = (self.modified, self.if_unmodified_since) {
+            if unix_secs(modified) > unix_secs(since) {
+                return Precondition::Failed;
+            }
+        }
+
+        if let Some(if_none_match) = self.if_none_match {
+            if etag_list_matches(if_none_match, self.etag, false) {
+                return Precondition::NotModified;
+            }
+        } else if let (Some(modified), Some(since)) = (self.modified, self.if_modified_since) {
+            if unix_secs(modified) <= unix_secs(since) {
+                return Precondition::NotModified;
+            }
+        }
+
+        Precondition::Proceed {
+            range: self
+                .if_range
+                .map_or(true, |if_range| self.if_range_matches(if_range)),
+        }
+    }
+
+    /// Whether an `If-Range` value matches, which requires a strong validator.
+    fn if_range_matches(&self, if_range: &str) -> bool {
+        let if_range = if_range.trim();
+        if if_range.starts_with('"') || if_range.starts_with("W/") {
+            return match (parse_etag(if_range), self.etag.and_then(parse_etag)) {
+                (Some((tag, "")), Some((current, _))) => strong_match(tag, current),
+                _ => false,
+            };
+        }
+
+        // A modification time is only a strong validator if the file was not modified again
+        // within the same second.
+        let (modified, since) = match (self.modified, httpdate::parse_http_date(if_range).ok()) {
+            (Some(modified), Some(since)) => (modified, since),
+            _ => return false,
+        };
+        let strong = modified
+            .checked_add(Duration::from_secs(1))
+            .is_some_and(|limit| limit <= SystemTime::now());
+        strong && unix_secs(modified) == unix_secs(since)
+    }
+}
+
+/// Whole seconds since the Unix epoch, because HTTP dates have no fractional part.
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+/// An entity tag, split into its weakness and opaque tag, including quotes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct EntityTag<'a> {
+    weak: bool,
+    opaque: &'a str,
+}
+
+/// Parse the entity tag at the start of the input, returning it with the remaining input.
+fn parse_etag(input: &str) -> Option<(EntityTag<'_>, &str)> {
+    let (weak, rest) = match input.strip_prefix("W/") {
+        Some(rest) => (true, rest),
+        None => (false, input),
+    };
+    let end = rest.strip_prefix('"')?.find('"')? + 2;
+    let etag = EntityTag {
+        weak,
+        opaque: &rest[..end],
+    };
+    Some((etag, rest[end..].trim_start()))
+}
+
+/// Whether a value is a single, well-formed entity tag, such as `"abc"` or `W/"abc"`.
+pub(crate) fn is_valid_etag(etag: &str) -> bool {
+    let opaque = etag.strip_prefix("W/").unwrap_or(etag);
+    match opaque
+        .strip_prefix('"')
+        .and_then(|rest| rest.strip_suffix('"'))
+    {
+        // Quotes, spaces and control characters are not allowed in the opaque tag.
+        Some(tag) => tag.bytes().all(|b| b == 0x21 || (0x23..=0x7e).contains(&b)),
+        None => false,
+    }
+}
+
+/// Strong comparison: both must be strong, and the opaque tags must be equal.
+fn strong_match(a: EntityTag, b: EntityTag) -> bool {
+    !a.weak && !b.weak && a.opaque == b.opaque
+}
+
+/// Weak comparison: the opaque tags must be equal.
+fn weak_match(a: EntityTag, b: EntityTag) -> bool {
+    a.opaque == b.opaque
+}
+
+/// Whether an `If-Match` or `If-None-Match` value matches the current entity tag.
+///
+/// The value is either `*`, which matches any current representation, or a list of entity tags.
+/// Malformed lists never match.
+fn etag_list_matches(list: &str, etag: Option<&str>, strong: bool) -> bool {
+    if list.trim() == "*" {
+        return true;
+    }
+    let current = match etag.and_then(parse_etag) {
+        Some((current, _)) => current,
+        None => return false,
+    };
+
+    // Empty list elements are allowed, and skipped.
+    let mut rest = list;
+    loop {
+        rest = rest.trim_start_matches(|c: char| c == ',' || c.is_ascii_whitespace());
+        if rest.is_empty() {
+            break;
+        }
+        let (candidate, next) = match parse_etag(rest) {
+            Some(res) => res,
+            None => return false,
+        };
+        let matches = if strong {
+            strong_match(candidate, current)
+        } else {
+            weak_match(candidate, current)
+        };
+        if matches {
+            return true;
+        }
+        if !next.is_empty() && !next.starts_with(',') {
+            return false;
+        }
+        rest = next;
+    }
+    false
+}
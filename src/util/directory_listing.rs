@@ -0,0 +1,172 @@
+use std::{
+    collections::HashSet, ffi::OsString, fmt::Write, ops::Range, path::Path, time::UNIX_EPOCH,
+};
+
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+
+use crate::{util::valid_mtime, DirectoryListing};
+
+/// Characters to percent-encode in links. Only unreserved characters are left as-is.
+const LINK_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// Escape a string for use in HTML text and attribute values.
+fn escape_html(input: &str) -> String {
+    let mut output = String::with_capacity(input.len());
+    for c in input.chars() {
+        match c {
+            '&' => output.push_str("&amp;"),
+            '<' => output.push_str("&lt;"),
+            '>' => output.push_str("&gt;"),
+            '"' => output.push_str("&quot;"),
+            '\'' => output.push_str("&#39;"),
+            c => output.push(c),
+        }
+    }
+    output
+}
+
+/// Write a string as a quoted JSON string.
+fn write_json_string(buf: &mut String, input: &str) {
+    buf.push('"');
+    for c in input.chars() {
+        match c {
+            '"' => buf.push_str("\\\""),
+            '\\' => buf.push_str("\\\\"),
+            '\n' => buf.push_str("\\n"),
+            '\r' => buf.push_str("\\r"),
+            '\t' => buf.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                write!(buf, "\\u{:04x}", c as u32).expect("buffer write failed");
+            }
+            c => buf.push(c),
+        }
+    }
+    buf.push('"');
+}
+
+/// Build the URL path of a directory, with leading and trailing slash.
+///
+/// When `encode` is set, components are percent-encoded for use in a link.
+pub(crate) fn directory_url_path(path: &Path, encode: bool) -> String {
+    // Append each component separately, so we don't end up with Windows path separators.
+    let mut target = String::with_capacity(path.as_os_str().len() + 2);
+    target.push('/');
+    for component in path.components() {
+        let component = component.as_os_str().to_string_lossy();
+        if encode {
+            target.extend(utf8_percent_encode(&component, LINK_ENCODE_SET));
+        } else {
+            target.push_str(&component);
+        }
+        target.push('/');
+    }
+    target
+}
+
+/// Render a directory listing as an HTML document.
+pub(crate) fn render_html_listing(listing: &DirectoryListing) -> String {
+    let title = escape_html(&directory_url_path(&listing.path, false));
+
+    let mut buf = String::with_capacity(512 + listing.entries.len() * 128);
+    write!(
+        &mut buf,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>Index of {title}</title>\n</head>\n<body>\n\
+         <h1>Index of {title}</h1>\n<table>\n\
+         <tr><th>Name</th><th>Size</th><th>Last modified</th></tr>\n"
+    )
+    .expect("buffer write failed");
+
+    // Links are absolute, because the listing may be served for a path without trailing slash.
+    // (See `Resolver::redirect_directories`.)
+    let base = directory_url_path(&listing.path, true);
+    if let Some(parent) = listing.path.parent() {
+        writeln!(
+            &mut buf,
+            "<tr><td><a href=\"{}\">../</a></td><td></td><td></td></tr>",
+            directory_url_path(parent, true)
+        )
+        .expect("buffer write failed");
+    }
+
+    for entry in &listing.entries {
+        let name = entry.name.to_string_lossy();
+        let slash = if entry.is_dir { "/" } else { "" };
+        let size = if entry.is_dir {
+            String::new()
+        } else {
+            entry.size.to_string()
+        };
+        let modified = valid_mtime(entry.modified)
+            .map(httpdate::fmt_http_date)
+            .unwrap_or_default();
+        writeln!(
+            &mut buf,
+            "<tr><td><a href=\"{base}{href}{slash}\">{name}{slash}</a></td><td>{size}</td><td>{modified}</td></tr>",
+            href = utf8_percent_encode(&name, LINK_ENCODE_SET),
+            name = escape_html(&name),
+        )
+        .expect("buffer write failed");
+    }
+
+    buf.push_str("</table>\n</body>\n</html>\n");
+    buf
+}
+
+/// Render a range of entries of a directory listing as a JSON array.
+///
+/// Pre-encoded variants are detected using all entries in the listing, not just the range, for each
+/// of the encodings of the listing.
+pub(crate) fn render_json_listing(listing: &DirectoryListing, range: Range<usize>) -> String {
+    let files: HashSet<&OsString> = listing
+        .entries
+        .iter()
+        .filter(|entry| !entry.is_dir)
+        .map(|entry| &entry.name)
+        .collect();
+
+    let mut buf = String::with_capacity(2 + range.len() * 128);
+    buf.push('[');
+    for (idx, entry) in listing.entries[range].iter().enumerate() {
+        if idx != 0 {
+            buf.push(',');
+        }
+
+        buf.push_str("{\"name\":");
+        write_json_string(&mut buf, &entry.name.to_string_lossy());
+
+        let kind = if entry.is_dir { "directory" } else { "file" };
+        write!(&mut buf, ",\"type\":\"{kind}\",\"size\":{}", entry.size)
+            .expect("buffer write failed");
+
+        match valid_mtime(entry.modified).and_then(|v| v.duration_since(UNIX_EPOCH).ok()) {
+            Some(modified) => {
+                write!(&mut buf, ",\"mtime\":{}", modified.as_secs()).expect("buffer write failed")
+            }
+            None => buf.push_str(",\"mtime\":null"),
+        }
+
+        buf.push_str(",\"variants\":[");
+        if !entry.is_dir {
+            let mut is_first = true;
+            for encoding in &listing.encodings {
+                let mut variant = entry.name.clone();
+                variant.push(encoding.suffix());
+                if files.contains(&variant) {
+                    if !is_first {
+                        buf.push(',');
+                    }
+                    is_first = false;
+                    write_json_string(&mut buf, encoding.token());
+                }
+            }
+        }
+        buf.push_str("]}");
+    }
+    buf.push(']');
+    buf
+}
@@ -173,12 +173,12 @@ impl<F> FileBytesStreamMultiRange<F> {
                 render_multipart_header(boundary, content_type, *range, is_first, file_length);
 
             is_first = false;
-            total_length += header.as_bytes().len() as u64;
+            total_length += header.len() as u64;
             total_length += range.length;
         }
 
         let header = render_multipart_header_end(boundary);
-        total_length += header.as_bytes().len() as u64;
+        total_length += header.len() as u64;
 
         total_length
     }
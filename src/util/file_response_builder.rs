@@ -1,4 +1,7 @@
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use http::{
     header, response::Builder as ResponseBuilder, HeaderMap, Method, Request, Response, Result,
@@ -8,9 +11,13 @@ use http_range::{HttpRange, HttpRangeParseError};
 use rand::prelude::{thread_rng, SliceRandom};
 
 use crate::{
-    util::{FileBytesStream, FileBytesStreamMultiRange, FileBytesStreamRange},
+    util::{
+        is_valid_etag, CompressedStream, Conditions, DecompressedStream, FileBytesStream,
+        FileBytesStreamMultiRange, FileBytesStreamRange, Precondition,
+    },
     vfs::IntoFileAccess,
-    Body, ResolvedFile,
+    AcceptEncoding, Body, CachePolicy, Compression, DefaultValidators, ResolvedFile,
+    ValidatorProvider,
 };
 
 /// Minimum duration since Unix epoch we accept for file modification time.
@@ -30,16 +37,28 @@ const BOUNDARY_CHARS: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijkl
 /// determine the response details.
 #[derive(Clone, Debug, Default)]
 pub struct FileResponseBuilder {
-    /// Whether to send cache headers, and what lifespan to indicate.
-    pub cache_headers: Option<u32>,
+    /// Rules for the `Cache-Control` header, if any.
+    pub cache_control: Option<Arc<CachePolicy>>,
     /// Whether this is a `HEAD` request, with no response body.
     pub is_head: bool,
+    /// The unparsed value of the `If-Match` request header.
+    pub if_match: Option<String>,
+    /// The unparsed value of the `If-None-Match` request header.
+    pub if_none_match: Option<String>,
     /// The parsed value of the `If-Modified-Since` request header.
     pub if_modified_since: Option<SystemTime>,
+    /// The parsed value of the `If-Unmodified-Since` request header.
+    pub if_unmodified_since: Option<SystemTime>,
     /// The file ranges to read, if any, otherwise we read from the beginning.
     pub range: Option<String>,
     /// The unparsed value of the `If-Range` request header. May match etag or last-modified.
     pub if_range: Option<String>,
+    /// The parsed value of the `Accept-Encoding` request header.
+    pub accept_encoding: AcceptEncoding,
+    /// Settings for compressing responses on the fly, if enabled.
+    pub compression: Option<Arc<Compression>>,
+    /// Provider of the `ETag` and `Last-Modified` values, if not `DefaultValidators`.
+    pub validators: Option<Arc<dyn ValidatorProvider>>,
 }
 
 impl FileResponseBuilder {
@@ -68,15 +87,39 @@ impl FileResponseBuilder {
 
     /// Apply parameters based on request headers.
     pub fn request_headers(&mut self, headers: &HeaderMap) -> &mut Self {
+        self.if_match = join_header_values(headers, header::IF_MATCH);
+        self.if_none_match = join_header_values(headers, header::IF_NONE_MATCH);
         self.if_modified_since_header(headers.get(header::IF_MODIFIED_SINCE));
+        self.if_unmodified_since_header(headers.get(header::IF_UNMODIFIED_SINCE));
         self.range_header(headers.get(header::RANGE));
         self.if_range(headers.get(header::IF_RANGE));
+        self.accept_encoding_header(headers.get(header::ACCEPT_ENCODING));
         self
     }
 
     /// Add cache headers to responses for the given lifespan.
+    ///
+    /// This is a shorthand for `cache_control` with `CachePolicy::max_age`.
     pub fn cache_headers(&mut self, value: Option<u32>) -> &mut Self {
-        self.cache_headers = value;
+        self.cache_control = value.map(|seconds| Arc::new(CachePolicy::max_age(seconds)));
+        self
+    }
+
+    /// Add cache headers to responses according to the given rules.
+    pub fn cache_control(&mut self, value: Option<Arc<CachePolicy>>) -> &mut Self {
+        self.cache_control = value;
+        self
+    }
+
+    /// Compress responses on the fly using the given settings.
+    pub fn compression(&mut self, value: Option<Arc<Compression>>) -> &mut Self {
+        self.compression = value;
+        self
+    }
+
+    /// Determine `ETag` and `Last-Modified` values using the given provider.
+    pub fn validators(&mut self, value: Option<Arc<dyn ValidatorProvider>>) -> &mut Self {
+        self.validators = value;
         self
     }
 
@@ -86,6 +129,18 @@ impl FileResponseBuilder {
         self
     }
 
+    /// Build responses for the given `If-Match` request header value.
+    pub fn if_match_header(&mut self, value: Option<&header::HeaderValue>) -> &mut Self {
+        self.if_match = value.and_then(|v| v.to_str().ok()).map(|v| v.to_string());
+        self
+    }
+
+    /// Build responses for the given `If-None-Match` request header value.
+    pub fn if_none_match_header(&mut self, value: Option<&header::HeaderValue>) -> &mut Self {
+        self.if_none_match = value.and_then(|v| v.to_str().ok()).map(|v| v.to_string());
+        self
+    }
+
     /// Build responses for the given `If-Modified-Since` date-time.
     pub fn if_modified_since(&mut self, value: Option<SystemTime>) -> &mut Self {
         self.if_modified_since = value;
@@ -100,6 +155,20 @@ impl FileResponseBuilder {
         self
     }
 
+    /// Build responses for the given `If-Unmodified-Since` date-time.
+    pub fn if_unmodified_since(&mut self, value: Option<SystemTime>) -> &mut Self {
+        self.if_unmodified_since = value;
+        self
+    }
+
+    /// Build responses for the given `If-Unmodified-Since` request header value.
+    pub fn if_unmodified_since_header(&mut self, value: Option<&header::HeaderValue>) -> &mut Self {
+        self.if_unmodified_since = value
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| httpdate::parse_http_date(v).ok());
+        self
+    }
+
     /// Build responses for the given `If-Range` request header value.
     pub fn if_range(&mut self, value: Option<&header::HeaderValue>) -> &mut Self {
         if let Some(s) = value.and_then(|s| s.to_str().ok()) {
@@ -108,6 +177,14 @@ impl FileResponseBuilder {
         self
     }
 
+    /// Build responses for the given `Accept-Encoding` request header value.
+    pub fn accept_encoding_header(&mut self, value: Option<&header::HeaderValue>) -> &mut Self {
+        self.accept_encoding = value
+            .map(AcceptEncoding::from_header_value)
+            .unwrap_or_default();
+        self
+    }
+
     /// Build responses for the given `Range` request header value.
     pub fn range_header(&mut self, value: Option<&header::HeaderValue>) -> &mut Self {
         self.range = value.and_then(|v| v.to_str().ok()).map(|v| v.to_string());
@@ -120,79 +197,138 @@ impl FileResponseBuilder {
         file: ResolvedFile<F>,
     ) -> Result<Response<Body<F::Output>>> {
         let mut res = ResponseBuilder::new();
-
-        // Set `Last-Modified` and check `If-Modified-Since`.
-        let modified = file.modified.filter(|v| {
-            v.duration_since(UNIX_EPOCH)
-                .ok()
-                .filter(|v| v >= &MIN_VALID_MTIME)
-                .is_some()
+        let (handle, file) = file.split_handle();
+
+        // Compression applies to files without a pre-encoded variant, but only full responses are
+        // actually compressed.
+        let compression = self.compression.as_ref().filter(|compression| {
+            file.encoding.is_none()
+                && file.decode.is_none()
+                && file.size >= compression.min_size
+                && file
+                    .content_type
+                    .as_ref()
+                    .is_some_and(|content_type| compression.applies_to(content_type))
         });
+        let compress = compression
+            .filter(|_| self.range.is_none())
+            .and_then(|compression| {
+                let encoding = compression.negotiate(&self.accept_encoding)?;
+                Some((encoding, compression.level))
+            });
+        let encoding = compress
+            .as_ref()
+            .map(|(encoding, _)| encoding)
+            .or(file.encoding.as_ref())
+            .cloned();
+
+        // Shared caches must key on `Accept-Encoding` if it could have changed the response.
+        let vary_encoding = file.vary_encoding || compression.is_some();
+        if vary_encoding {
+            res = res.header(header::VARY, "Accept-Encoding");
+        }
 
-        // default to false when specified, either the etag or last_modified will set
-        // it to true later.
-        let mut range_cond_ok = self.if_range.is_none();
-        if let Some(modified) = modified {
-            if let Ok(modified_unix) = modified.duration_since(UNIX_EPOCH) {
-                // Compare whole seconds only, because the HTTP date-time
-                // format also does not contain a fractional part.
-                if let Some(Ok(ims_unix)) =
-                    self.if_modified_since.map(|v| v.duration_since(UNIX_EPOCH))
-                {
-                    if modified_unix.as_secs() <= ims_unix.as_secs() {
-                        return ResponseBuilder::new()
-                            .status(StatusCode::NOT_MODIFIED)
-                            .body(Body::Empty);
-                    }
-                }
-
-                let etag = format!(
-                    "W/\"{0:x}-{1:x}.{2:x}\"",
-                    file.size,
-                    modified_unix.as_secs(),
-                    modified_unix.subsec_nanos()
-                );
-                if let Some(ref v) = self.if_range {
-                    if *v == etag {
-                        range_cond_ok = true;
-                    }
-                }
+        // The client may refuse the identity encoding, but we found no other way to send the file.
+        if encoding.is_none() && !self.accept_encoding.accepts_identity() {
+            return res.status(StatusCode::NOT_ACCEPTABLE).body(Body::Empty);
+        }
 
-                res = res.header(header::ETAG, etag);
+        // Each encoding is a separate representation, with its own entity tag. The default tags
+        // account for the encoding, but tags from a provider are suffixed with it, and replaced
+        // with the default tag if malformed. The validators describe the file as resolved, so are
+        // only strong if the file contents are sent as-is.
+        let validators = match self.validators {
+            Some(ref provider) => {
+                let mut validators = provider.validators(&file);
+                validators.etag = match validators.etag {
+                    Some(etag) if is_valid_etag(&etag) => Some(match file.encoding {
+                        Some(ref enc) => format!("{}-{}\"", &etag[..etag.len() - 1], enc.token()),
+                        None => etag,
+                    }),
+                    Some(_) => DefaultValidators.validators(&file).etag,
+                    None => None,
+                };
+                validators
             }
-
-            let last_modified_formatted = httpdate::fmt_http_date(modified);
-            if let Some(ref v) = self.if_range {
-                if *v == last_modified_formatted {
-                    range_cond_ok = true;
-                }
+            None => DefaultValidators.validators(&file),
+        };
+        let modified = validators.last_modified;
+        let etag = validators.etag.map(|etag| {
+            if compress.is_none() && file.decode.is_none() {
+                return etag;
             }
+            format!(
+                "W/\"{}-{}\"",
+                etag.trim_start_matches("W/").trim_matches('"'),
+                encoding.as_ref().map_or("identity", |enc| enc.token())
+            )
+        });
 
-            res = res
-                .header(header::LAST_MODIFIED, last_modified_formatted)
-                .header(header::ACCEPT_RANGES, "bytes");
+        // Evaluate conditional request headers.
+        let conditions = Conditions {
+            etag: etag.as_deref(),
+            modified,
+            if_match: self.if_match.as_deref(),
+            if_none_match: self.if_none_match.as_deref(),
+            if_modified_since: self.if_modified_since,
+            if_unmodified_since: self.if_unmodified_since,
+            if_range: self.if_range.as_deref(),
+        };
+        let precondition = conditions.evaluate();
+        if precondition == Precondition::Failed {
+            return ResponseBuilder::new()
+                .status(StatusCode::PRECONDITION_FAILED)
+                .body(Body::Empty);
         }
 
-        // Build remaining headers.
-        if let Some(seconds) = self.cache_headers {
-            res = res.header(
-                header::CACHE_CONTROL,
-                format!("public, max-age={}", seconds),
-            );
+        // Validator and cache headers are shared with `304 Not Modified` responses, so caches can
+        // refresh their stored response.
+        if let Some(etag) = etag {
+            res = res.header(header::ETAG, etag);
+        }
+        if let Some(modified) = modified {
+            res = res.header(header::LAST_MODIFIED, httpdate::fmt_http_date(modified));
+        }
+        if let Some(value) = self
+            .cache_control
+            .as_ref()
+            .and_then(|policy| policy.select(&file))
+        {
+            res = res.header(header::CACHE_CONTROL, value.to_string());
+        }
+
+        let range_cond_ok = match precondition {
+            Precondition::Proceed { range } => range,
+            _ => return res.status(StatusCode::NOT_MODIFIED).body(Body::Empty),
+        };
+
+        // Build remaining headers. Ranges are never served from bodies that are compressed or
+        // decompressed on the fly.
+        if modified.is_some() && file.decode.is_none() && compress.is_none() {
+            res = res.header(header::ACCEPT_RANGES, "bytes");
+        }
+        if let Some(encoding) = encoding {
+            res = res.header(header::CONTENT_ENCODING, encoding.to_header_value());
         }
 
         if self.is_head {
-            res = res.header(header::CONTENT_LENGTH, format!("{}", file.size));
+            // The compressed or decompressed length is not known in advance.
+            if compress.is_none() && file.decode.is_none() {
+                res = res.header(header::CONTENT_LENGTH, format!("{}", file.size));
+            }
             return res.status(StatusCode::OK).body(Body::Empty);
         }
 
-        let ranges = self.range.as_ref().filter(|_| range_cond_ok).and_then(|r| {
-            match HttpRange::parse(r, file.size) {
+        // Ranges of decompressed files are not supported, so we serve the full file instead.
+        let ranges = self
+            .range
+            .as_ref()
+            .filter(|_| range_cond_ok && file.decode.is_none())
+            .and_then(|r| match HttpRange::parse(r, file.size) {
                 Ok(r) => Some(Ok(r)),
                 Err(HttpRangeParseError::NoOverlap) => Some(Err(())),
                 Err(HttpRangeParseError::InvalidRange) => None,
-            }
-        });
+            });
 
         if let Some(ranges) = ranges {
             let ranges = match ranges {
@@ -214,8 +350,7 @@ impl FileResponseBuilder {
                     )
                     .header(header::CONTENT_LENGTH, format!("{}", single_span.length));
 
-                let body_stream =
-                    FileBytesStreamRange::new(file.handle.into_file_access(), single_span);
+                let body_stream = FileBytesStreamRange::new(handle.into_file_access(), single_span);
                 return res
                     .status(StatusCode::PARTIAL_CONTENT)
                     .body(Body::Range(body_stream));
@@ -237,7 +372,7 @@ impl FileResponseBuilder {
                 );
 
                 let mut body_stream = FileBytesStreamMultiRange::new(
-                    file.handle.into_file_access(),
+                    handle.into_file_access(),
                     ranges,
                     boundary,
                     file.size,
@@ -257,23 +392,83 @@ impl FileResponseBuilder {
             }
         }
 
-        res = res.header(header::CONTENT_LENGTH, format!("{}", file.size));
         if let Some(content_type) = file.content_type {
             res = res.header(header::CONTENT_TYPE, content_type);
         }
-        if let Some(encoding) = file.encoding {
-            res = res.header(header::CONTENT_ENCODING, encoding.to_header_value());
+
+        // The compressed length is not known in advance.
+        if let Some((encoding, level)) = compress {
+            let body_stream = match CompressedStream::new(
+                FileBytesStream::new_with_limit(handle.into_file_access(), file.size),
+                encoding,
+                level,
+            ) {
+                Ok(body_stream) => body_stream,
+                // Creating the encoder can fail, for example if it could not allocate memory.
+                Err(_) => return internal_server_error(),
+            };
+            return res
+                .status(StatusCode::OK)
+                .body(Body::Compressed(body_stream));
         }
 
+        if let Some(encoding) = file.decode {
+            let body_stream = match DecompressedStream::new(
+                FileBytesStream::new_with_limit(handle.into_file_access(), file.size),
+                encoding,
+            ) {
+                Ok(body_stream) => body_stream,
+                // The resolver only decodes available encodings, but creating the decoder may
+                // still fail, for example if it could not allocate memory.
+                Err(_) => return internal_server_error(),
+            };
+            return res
+                .status(StatusCode::OK)
+                .body(Body::Decompressed(body_stream));
+        }
+
+        res = res.header(header::CONTENT_LENGTH, format!("{}", file.size));
+
         // Stream the body.
         res.status(StatusCode::OK)
             .body(Body::Full(FileBytesStream::new_with_limit(
-                file.handle.into_file_access(),
+                handle.into_file_access(),
                 file.size,
             )))
     }
 }
 
+/// Build an empty `500 Internal Server Error` response.
+fn internal_server_error<F>() -> Result<Response<Body<F>>> {
+    ResponseBuilder::new()
+        .status(StatusCode::INTERNAL_SERVER_ERROR)
+        .body(Body::Empty)
+}
+
+/// Combine the values of a header that may be sent multiple times as a list.
+fn join_header_values(headers: &HeaderMap, name: header::HeaderName) -> Option<String> {
+    let values: Vec<_> = headers
+        .get_all(name)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .collect();
+    if values.is_empty() {
+        None
+    } else {
+        Some(values.join(", "))
+    }
+}
+
+/// Discard modification times we consider invalid. (See `MIN_VALID_MTIME`.)
+pub(crate) fn valid_mtime(modified: Option<SystemTime>) -> Option<SystemTime> {
+    modified.filter(|v| {
+        v.duration_since(UNIX_EPOCH)
+            .ok()
+            .filter(|v| v >= &MIN_VALID_MTIME)
+            .is_some()
+    })
+}
+
 fn content_range_header(r: &HttpRange, total_length: u64) -> String {
     format!(
         "bytes {}-{}/{}",
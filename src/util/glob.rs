@@ -0,0 +1,184 @@
+use std::path::Path;
+
+/// A simple glob pattern for matching request paths.
+///
+/// Supported syntax:
+///  - `*` matches any sequence of characters, except `/`.
+///  - `**` matches any sequence of characters, including `/`.
+///  - `?` matches any single character, except `/`.
+///  - `[abc]` and `[a-z]` match one of the listed characters, except `/`. Classes starting with
+///    `!` or `^` are negated. A `[` without closing `]` matches literally.
+///  - Everything else matches literally.
+///
+/// Patterns containing a `/` are matched against the full path, which always starts with a `/`.
+/// Patterns without a `/` are matched against just the last path component, so `*.map` matches
+/// source maps in any directory.
+///
+/// Matching takes time proportional to the length of the pattern times the length of the path,
+/// regardless of the number of wildcards.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Glob {
+    tokens: Vec<Token>,
+    full_path: bool,
+}
+
+/// A parsed element of a glob pattern.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    Literal(char),
+    /// `?`
+    Any,
+    /// `*`
+    Star,
+    /// `**`, where a following `/` may also be skipped, so `**/` matches zero directories.
+    DoubleStar,
+    /// `[...]`, as inclusive ranges.
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+}
+
+impl Token {
+    /// Whether the token matches a single character.
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Token::Literal(literal) => *literal == c,
+            Token::Any => c != '/',
+            Token::Class { negated, ranges } => {
+                c != '/' && ranges.iter().any(|(lo, hi)| (*lo..=*hi).contains(&c)) != *negated
+            }
+            Token::Star | Token::DoubleStar => false,
+        }
+    }
+}
+
+/// Parse a glob pattern into tokens.
+fn parse(pattern: &str) -> Vec<Token> {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut tokens = Vec::with_capacity(chars.len());
+    let mut idx = 0;
+    while idx < chars.len() {
+        match chars[idx] {
+            '*' if chars.get(idx + 1) == Some(&'*') => {
+                tokens.push(Token::DoubleStar);
+                idx += 2;
+            }
+            '*' => {
+                tokens.push(Token::Star);
+                idx += 1;
+            }
+            '?' => {
+                tokens.push(Token::Any);
+                idx += 1;
+            }
+            '[' => match parse_class(&chars[idx + 1..]) {
+                Some((token, len)) => {
+                    tokens.push(token);
+                    idx += len + 1;
+                }
+                None => {
+                    tokens.push(Token::Literal('['));
+                    idx += 1;
+                }
+            },
+            c => {
+                tokens.push(Token::Literal(c));
+                idx += 1;
+            }
+        }
+    }
+    tokens
+}
+
+/// Parse a character class following a `[`, returning the token and the number of characters
+/// used, including the closing `]`.
+fn parse_class(chars: &[char]) -> Option<(Token, usize)> {
+    let negated = matches!(chars.first(), Some('!') | Some('^'));
+    let mut idx = usize::from(negated);
+    let mut ranges = Vec::new();
+    loop {
+        let c = *chars.get(idx)?;
+        // A `]` right after the opening bracket is a literal.
+        if c == ']' && idx > usize::from(negated) {
+            return Some((Token::Class { negated, ranges }, idx + 1));
+        }
+        match (chars.get(idx + 1), chars.get(idx + 2)) {
+            (Some('-'), Some(&hi)) if hi != ']' => {
+                ranges.push((c, hi));
+                idx += 3;
+            }
+            _ => {
+                ranges.push((c, c));
+                idx += 1;
+            }
+        }
+    }
+}
+
+impl Glob {
+    /// Create a glob from a pattern.
+    pub fn new(pattern: &str) -> Self {
+        Self {
+            tokens: parse(pattern),
+            full_path: pattern.contains('/'),
+        }
+    }
+
+    /// Test a URL path against the pattern.
+    pub fn is_match(&self, path: &str) -> bool {
+        let text: Vec<char> = if self.full_path {
+            path.chars().collect()
+        } else {
+            path.rsplit('/').next().unwrap_or(path).chars().collect()
+        };
+        match_tokens(&self.tokens, &text)
+    }
+
+    /// Test a sanitized path, as used by `Resolver`, against the pattern.
+    pub fn is_match_path(&self, path: &Path) -> bool {
+        let mut url_path = String::with_capacity(path.as_os_str().len() + 1);
+        for component in path.components() {
+            url_path.push('/');
+            url_path.push_str(&component.as_os_str().to_string_lossy());
+        }
+        if url_path.is_empty() {
+            url_path.push('/');
+        }
+        self.is_match(&url_path)
+    }
+}
+
+impl From<&str> for Glob {
+    fn from(pattern: &str) -> Self {
+        Self::new(pattern)
+    }
+}
+
+/// Match text against tokens, using dynamic programming instead of backtracking.
+///
+/// Each row holds, for every position in the text, whether the rest of the text matches the
+/// tokens from a given index onwards. Rows are built from the last token to the first.
+fn match_tokens(tokens: &[Token], text: &[char]) -> bool {
+    let len = text.len();
+    // Rows for the tokens at `idx + 1` and `idx + 2`.
+    let mut next: Vec<bool> = (0..=len).map(|pos| pos == len).collect();
+    let mut after_next = vec![false; len + 1];
+    let mut row = vec![false; len + 1];
+
+    for (idx, token) in tokens.iter().enumerate().rev() {
+        for pos in (0..=len).rev() {
+            row[pos] = match token {
+                Token::Star => next[pos] || (pos < len && text[pos] != '/' && row[pos + 1]),
+                Token::DoubleStar => {
+                    let skip_slash = tokens.get(idx + 1) == Some(&Token::Literal('/'));
+                    next[pos] || (skip_slash && after_next[pos]) || (pos < len && row[pos + 1])
+                }
+                _ => pos < len && token.matches(text[pos]) && next[pos + 1],
+            };
+        }
+        std::mem::swap(&mut after_next, &mut next);
+        std::mem::swap(&mut next, &mut row);
+    }
+    next[0]
+}
@@ -1,8 +1,16 @@
+mod compressed_stream;
+mod conditional;
+mod directory_listing;
 mod file_bytes_stream;
 mod file_response_builder;
+mod glob;
 mod requested_path;
 
+pub use self::compressed_stream::*;
 pub use self::file_bytes_stream::*;
 pub use self::file_response_builder::*;
+pub use self::glob::*;
 
+pub(crate) use self::conditional::*;
+pub(crate) use self::directory_listing::*;
 pub(crate) use self::requested_path::*;
@@ -7,7 +7,7 @@ fn decode_percents(string: &str) -> String {
         .into_owned()
 }
 
-fn sanitize_path(path: &Path) -> PathBuf {
+pub(crate) fn sanitize_path(path: &Path) -> PathBuf {
     path.components()
         .fold(PathBuf::new(), |mut result, p| match p {
             Component::Normal(x) => {
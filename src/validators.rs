@@ -0,0 +1,96 @@
+use std::{
+    fmt,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{util::valid_mtime, ResolvedFile};
+
+/// Validators of a file, used in conditional requests.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Validators {
+    /// The `ETag` value, including quotes and an optional `W/` prefix.
+    pub etag: Option<String>,
+    /// The `Last-Modified` time.
+    pub last_modified: Option<SystemTime>,
+}
+
+/// Determines the validators of files, for the `ETag` and `Last-Modified` headers.
+///
+/// The validators describe the file as resolved. So each representation has its own entity tag,
+/// the token of the encoding is appended to the tag given here for pre-encoded files, such as
+/// `"build-1-gzip"`. If the response is compressed or decompressed on the fly, the entity tag is
+/// derived the same way, and made weak. Entity tags that are not of the form `"..."` or `W/"..."`
+/// are replaced with the default ones. The file handle is not available to providers, only the
+/// metadata.
+///
+/// This is implemented for closures, so a provider can be as simple as:
+///
+/// ```rust
+/// use std::time::{Duration, UNIX_EPOCH};
+///
+/// use hyper_staticfile::{ContentHash, ResolvedFile, Static, Validators};
+///
+/// // For reproducible builds: content hashes, and the time from `SOURCE_DATE_EPOCH`.
+/// let build_time = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+/// let mut static_ = Static::new("public/");
+/// static_
+///     .content_hash(Some(ContentHash::Xxh3))
+///     .validators(move |file: &ResolvedFile<()>| Validators {
+///         etag: file.content_hash.as_ref().map(|hash| format!("\"{}\"", hash)),
+///         last_modified: Some(build_time),
+///     });
+/// ```
+pub trait ValidatorProvider: Send + Sync {
+    /// Determine the validators of a resolved file.
+    fn validators(&self, file: &ResolvedFile<()>) -> Validators;
+}
+
+impl<T> ValidatorProvider for T
+where
+    T: Fn(&ResolvedFile<()>) -> Validators + Send + Sync,
+{
+    fn validators(&self, file: &ResolvedFile<()>) -> Validators {
+        self(file)
+    }
+}
+
+impl fmt::Debug for dyn ValidatorProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ValidatorProvider")
+    }
+}
+
+/// The default validators, based on file metadata.
+///
+/// If the file has a content hash, it is used as a strong entity tag. (See
+/// `Resolver::content_hash`.) Otherwise, a weak entity tag is derived from the size and
+/// modification time. The modification time is used as-is for `Last-Modified`, unless it is
+/// obviously invalid, such as a zero value.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DefaultValidators;
+
+impl ValidatorProvider for DefaultValidators {
+    fn validators(&self, file: &ResolvedFile<()>) -> Validators {
+        let last_modified = valid_mtime(file.modified);
+        let etag = match file.content_hash {
+            Some(ref hash) => Some(format!("\"{}\"", hash)),
+            None => last_modified
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map(|modified_unix| {
+                    format!(
+                        "W/\"{0:x}-{1:x}.{2:x}{3}\"",
+                        file.size,
+                        modified_unix.as_secs(),
+                        modified_unix.subsec_nanos(),
+                        file.encoding
+                            .as_ref()
+                            .map_or(String::new(), |enc| format!("-{}", enc.token()))
+                    )
+                }),
+        };
+        Validators {
+            etag,
+            last_modified,
+        }
+    }
+}
@@ -1,29 +1,48 @@
 use std::{
     cmp::min,
     collections::HashMap,
+    ffi::OsString,
     fs::OpenOptions,
     future::Future,
     io::{Cursor, Error, ErrorKind},
     mem::MaybeUninit,
     path::{Component, Path, PathBuf},
     pin::Pin,
+    sync::Arc,
     task::{Context, Poll},
     time::SystemTime,
 };
 
-use futures_util::future::{ready, Ready};
+use futures_util::future::{ready, BoxFuture, Ready};
 use hyper::body::Bytes;
+use mime_guess::MimeGuess;
 use tokio::{
     fs::{self, File},
     io::{AsyncRead, AsyncSeek, ReadBuf},
     task::{spawn_blocking, JoinHandle},
 };
 
+use crate::{compression::is_available, util::compress_slice, Compression, ContentHash};
+
 #[cfg(windows)]
 use std::os::windows::fs::OpenOptionsExt;
 #[cfg(windows)]
 use winapi::um::winbase::FILE_FLAG_BACKUP_SEMANTICS;
 
+mod handle_cache;
+pub use self::handle_cache::*;
+
+mod overlay;
+pub use self::overlay::*;
+
+mod variant_cache;
+pub use self::variant_cache::*;
+
+#[cfg(target_os = "linux")]
+mod openat2;
+#[cfg(target_os = "linux")]
+pub use self::openat2::*;
+
 const TOKIO_READ_BUF_SIZE: usize = 8 * 1024;
 
 /// Open file handle with metadata.
@@ -31,8 +50,10 @@ const TOKIO_READ_BUF_SIZE: usize = 8 * 1024;
 /// This struct exists because we want to abstract away tokio `File`, but need to use
 /// `File`-specific operations to find the metadata and fill the additional fields here.
 ///
-/// This struct is eventually converted to a `ResolvedFile`.
+/// This struct is eventually converted to a `ResolvedFile`. Openers outside this crate create it
+/// with `FileWithMetadata::new`, so fields can be added without breaking them.
 #[derive(Debug)]
+#[non_exhaustive]
 pub struct FileWithMetadata<F = File> {
     /// Open file handle.
     pub handle: F,
@@ -42,12 +63,46 @@ pub struct FileWithMetadata<F = File> {
     pub modified: Option<SystemTime>,
     /// Whether this is a directory.
     pub is_dir: bool,
+    /// Precomputed hash of the file contents, if known. (See `Resolver::content_hash`.)
+    pub content_hash: Option<Arc<str>>,
+}
+
+impl<F> FileWithMetadata<F> {
+    /// Create a `FileWithMetadata` from a handle and its metadata.
+    ///
+    /// Optional fields, such as `content_hash`, are left empty, and can be set afterwards.
+    pub fn new(handle: F, size: u64, modified: Option<SystemTime>, is_dir: bool) -> Self {
+        Self {
+            handle,
+            size,
+            modified,
+            is_dir,
+            content_hash: None,
+        }
+    }
+}
+
+/// A single entry in a directory listing.
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    /// File name of the entry.
+    pub name: OsString,
+    /// Size in bytes.
+    pub size: u64,
+    /// Last modification time.
+    pub modified: Option<SystemTime>,
+    /// Whether this is a directory.
+    pub is_dir: bool,
 }
 
+/// Future type that `FileOpener::read_dir` returns.
+pub type ReadDirFuture = BoxFuture<'static, Result<Vec<DirEntry>, Error>>;
+
 /// Trait for a simple virtual filesystem layer.
 ///
-/// There is only the `open` operation, hence the name `FileOpener`. In practice, `open` must also
-/// collect some file metadata. (See the `FileWithMetadata` struct.)
+/// The main operation is `open`, hence the name `FileOpener`. In practice, `open` must also
+/// collect some file metadata. (See the `FileWithMetadata` struct.) Directory listings are
+/// supported through the optional `read_dir` operation.
 pub trait FileOpener: Send + Sync + 'static {
     /// File handle type.
     type File: IntoFileAccess;
@@ -59,6 +114,19 @@ pub trait FileOpener: Send + Sync + 'static {
     ///
     /// It can be assumed the path is already sanitized at this point.
     fn open(&self, path: &Path) -> Self::Future;
+
+    /// List the entries of a directory, in no particular order.
+    ///
+    /// It can be assumed the path is already sanitized at this point.
+    ///
+    /// The default implementation returns an error of kind `ErrorKind::Unsupported`, in which case
+    /// directories without index are not found, as if `Resolver::autoindex` was disabled.
+    fn read_dir(&self, _path: &Path) -> ReadDirFuture {
+        Box::pin(ready(Err(Error::new(
+            ErrorKind::Unsupported,
+            "directory listings are not supported",
+        ))))
+    }
 }
 
 /// Trait that converts a file handle into something that implements `FileAccess`.
@@ -159,12 +227,33 @@ impl FileAccess for TokioFileAccess {
     }
 }
 
+/// Policy for following symlinks in `TokioFileOpener`.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum SymlinkPolicy {
+    /// Always follow symlinks, even if the target is outside the root.
+    #[default]
+    Follow,
+    /// Follow symlinks only if the target is inside the root.
+    ///
+    /// Both the root and the target are canonicalized for the comparison, so the root itself may
+    /// be a symlink.
+    FollowWithinRoot,
+    /// Never follow symlinks inside the root.
+    Never,
+}
+
 /// Filesystem implementation that uses `tokio::fs`.
 pub struct TokioFileOpener {
     /// The virtual root directory to use when opening files.
     ///
     /// The path may be absolute or relative.
     pub root: PathBuf,
+
+    /// Whether to follow symlinks inside the root. Defaults to `SymlinkPolicy::Follow`.
+    ///
+    /// Refused symlinks result in a `PermissionDenied` error. Note that these checks are done
+    /// before opening the file, so they can race with changes to the filesystem.
+    pub symlinks: SymlinkPolicy,
 }
 
 impl TokioFileOpener {
@@ -172,7 +261,46 @@ impl TokioFileOpener {
     ///
     /// The path may be absolute or relative.
     pub fn new(root: impl Into<PathBuf>) -> Self {
-        Self { root: root.into() }
+        Self {
+            root: root.into(),
+            symlinks: SymlinkPolicy::default(),
+        }
+    }
+}
+
+/// Error returned when the symlink policy refuses access.
+fn symlink_refused() -> Error {
+    Error::new(ErrorKind::PermissionDenied, "symlink refused by policy")
+}
+
+/// Check whether the symlink policy allows access to a path inside the root.
+///
+/// This is a blocking operation.
+fn check_symlinks(root: &Path, path: &Path, policy: SymlinkPolicy) -> Result<(), Error> {
+    match policy {
+        SymlinkPolicy::Follow => Ok(()),
+        SymlinkPolicy::FollowWithinRoot => {
+            let mut full_path = root.to_path_buf();
+            full_path.extend(path);
+            if full_path.canonicalize()?.starts_with(root.canonicalize()?) {
+                Ok(())
+            } else {
+                Err(symlink_refused())
+            }
+        }
+        SymlinkPolicy::Never => {
+            let mut full_path = root.to_path_buf();
+            for component in path.components() {
+                full_path.push(component);
+                if std::fs::symlink_metadata(&full_path)?
+                    .file_type()
+                    .is_symlink()
+                {
+                    return Err(symlink_refused());
+                }
+            }
+            Ok(())
+        }
     }
 }
 
@@ -181,12 +309,18 @@ impl FileOpener for TokioFileOpener {
     type Future = TokioFileFuture;
 
     fn open(&self, path: &Path) -> Self::Future {
-        let mut full_path = self.root.clone();
-        full_path.extend(path);
+        let root = self.root.clone();
+        let path = path.to_path_buf();
+        let symlinks = self.symlinks;
 
         // Small perf gain: we do open + metadata in one go. If we used the tokio async functions
         // here, that'd amount to two `spawn_blocking` calls behind the scenes.
         let inner = spawn_blocking(move || {
+            check_symlinks(&root, &path, symlinks)?;
+
+            let mut full_path = root;
+            full_path.extend(&path);
+
             let mut opts = OpenOptions::new();
             opts.read(true);
 
@@ -201,11 +335,70 @@ impl FileOpener for TokioFileOpener {
                 size: metadata.len(),
                 modified: metadata.modified().ok(),
                 is_dir: metadata.is_dir(),
+                content_hash: None,
             })
         });
 
         TokioFileFuture { inner }
     }
+
+    fn read_dir(&self, path: &Path) -> ReadDirFuture {
+        let root = self.root.clone();
+        let path = path.to_path_buf();
+        let symlinks = self.symlinks;
+
+        let inner = spawn_blocking(move || {
+            check_symlinks(&root, &path, symlinks)?;
+
+            let mut full_path = root.clone();
+            full_path.extend(&path);
+
+            let mut entries = Vec::new();
+            for entry in std::fs::read_dir(full_path)? {
+                let entry = entry?;
+
+                // Leave out symlinks that `open` would refuse.
+                if entry.file_type()?.is_symlink()
+                    && check_symlinks(&root, &path.join(entry.file_name()), symlinks).is_err()
+                {
+                    continue;
+                }
+
+                // Follow symlinks, like `open` does. Entries we can't stat (such as dangling
+                // symlinks) are left out of the listing.
+                let metadata = match std::fs::metadata(entry.path()) {
+                    Ok(metadata) => metadata,
+                    Err(_) => continue,
+                };
+                entries.push(DirEntry {
+                    name: entry.file_name(),
+                    size: metadata.len(),
+                    modified: metadata.modified().ok(),
+                    is_dir: metadata.is_dir(),
+                });
+            }
+            Ok(entries)
+        });
+
+        Box::pin(TokioReadDirFuture { inner })
+    }
+}
+
+/// Flatten the result of a `spawn_blocking` task that itself returns an IO result.
+///
+/// The task produces a result, but so does the `JoinHandle`, so this is a `Result<Result<..>>`.
+/// We map the `JoinHandle` error to an IO error, so that we can flatten the results. This is
+/// similar to what tokio does, but that just uses `Map` and async functions (with an anonymous
+/// future type).
+fn poll_blocking<T>(
+    inner: &mut JoinHandle<Result<T, Error>>,
+    cx: &mut Context<'_>,
+) -> Poll<Result<T, Error>> {
+    match Pin::new(inner).poll(cx) {
+        Poll::Ready(Ok(res)) => Poll::Ready(res),
+        Poll::Ready(Err(_)) => Poll::Ready(Err(Error::other("background task failed"))),
+        Poll::Pending => Poll::Pending,
+    }
 }
 
 /// Future type produced by `TokioFileOpener`.
@@ -219,17 +412,20 @@ impl Future for TokioFileFuture {
     type Output = Result<FileWithMetadata<File>, Error>;
 
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        // The task produces a result, but so does the `JoinHandle`, so this is a
-        // `Result<Result<..>>`. We map the `JoinHandle` error to an IO error, so that we can
-        // flatten the results. This is similar to what tokio does, but that just uses `Map` and
-        // async functions (with an anonymous future type).
-        match Pin::new(&mut self.inner).poll(cx) {
-            Poll::Ready(Ok(res)) => Poll::Ready(res),
-            Poll::Ready(Err(_)) => {
-                Poll::Ready(Err(Error::new(ErrorKind::Other, "background task failed")))
-            }
-            Poll::Pending => Poll::Pending,
-        }
+        poll_blocking(&mut self.inner, cx)
+    }
+}
+
+/// Future type of the blocking task in `TokioFileOpener::read_dir`.
+pub(crate) struct TokioReadDirFuture {
+    inner: JoinHandle<Result<Vec<DirEntry>, Error>>,
+}
+
+impl Future for TokioReadDirFuture {
+    type Output = Result<Vec<DirEntry>, Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        poll_blocking(&mut self.inner, cx)
     }
 }
 
@@ -289,6 +485,7 @@ impl Default for MemoryFs {
                 size: 0,
                 modified: None,
                 is_dir: true,
+                content_hash: None,
             },
         );
 
@@ -328,6 +525,93 @@ impl MemoryFs {
         Ok(fs)
     }
 
+    /// Generate compressed variants of the files currently loaded.
+    ///
+    /// Variants are generated for each encoding of the `Compression` settings that is available,
+    /// and stored alongside the original with the suffix of the encoding, such as `app.js.gz`. This
+    /// is where `Resolver` looks for pre-encoded files, so the encodings must also be allowed there.
+    /// A variant is only kept if it is smaller than the original, and existing files are never
+    /// overwritten.
+    ///
+    /// ```rust
+    /// use hyper_staticfile::{vfs::MemoryFs, Compression, CompressionLevel};
+    ///
+    /// # async fn load() -> std::io::Result<MemoryFs> {
+    /// let mut fs = MemoryFs::from_dir("public/").await?;
+    /// fs.precompress(&Compression::new().level(CompressionLevel::Best))
+    ///     .await?;
+    /// # Ok(fs)
+    /// # }
+    /// ```
+    pub async fn precompress(&mut self, compression: &Compression) -> Result<&mut Self, Error> {
+        let encodings: Vec<_> = compression
+            .encodings
+            .preferred()
+            .into_iter()
+            .map(|(enc, _)| enc)
+            .filter(is_available)
+            .collect();
+
+        let mut variants = Vec::new();
+        for (path, file) in &self.files {
+            if file.is_dir || file.size < compression.min_size {
+                continue;
+            }
+            match MimeGuess::from_path(path).first() {
+                Some(mimetype) if compression.applies_to(mimetype.as_ref()) => {}
+                _ => continue,
+            }
+
+            for enc in &encodings {
+                let mut enc_path = path.clone().into_os_string();
+                enc_path.push(enc.suffix());
+                let enc_path = PathBuf::from(enc_path);
+                if self.files.contains_key(&enc_path) {
+                    continue;
+                }
+
+                // Compression is CPU-bound, so run it on a blocking thread.
+                let (data, enc, level) = (file.handle.clone(), enc.clone(), compression.level);
+                let data = spawn_blocking(move || compress_slice(&data, enc, level))
+                    .await
+                    .map_err(Error::other)??;
+                if (data.len() as u64) < file.size {
+                    variants.push((enc_path, data, file.modified));
+                }
+            }
+        }
+
+        for (path, data, modified) in variants {
+            self.add(path, data, modified);
+        }
+        Ok(self)
+    }
+
+    /// Hash the contents of the files currently loaded.
+    ///
+    /// The hashes are used for strong entity tags, so `Resolver` doesn't have to compute them when
+    /// the files are first served. Files added later, including variants from `precompress`, are
+    /// not hashed, so this is typically called last. Does nothing if the algorithm is not
+    /// available. (See `ContentHash`.)
+    ///
+    /// ```rust
+    /// use hyper_staticfile::{vfs::MemoryFs, ContentHash};
+    ///
+    /// # async fn load() -> std::io::Result<MemoryFs> {
+    /// let mut fs = MemoryFs::from_dir("public/").await?;
+    /// fs.precompute_hashes(ContentHash::Xxh3);
+    /// # Ok(fs)
+    /// # }
+    /// ```
+    pub fn precompute_hashes(&mut self, algo: ContentHash) -> &mut Self {
+        for file in self.files.values_mut() {
+            if !file.is_dir {
+                file.content_hash = algo.hash(&file.handle);
+            }
+        }
+        self
+    }
+
     /// Add a file to the `MemoryFs`.
     ///
     /// This automatically creates directory entries leading up to the path. Any existing entries
@@ -354,6 +638,7 @@ impl MemoryFs {
                         size: 0,
                         modified: None,
                         is_dir: true,
+                        content_hash: None,
                     },
                 );
             }
@@ -368,6 +653,7 @@ impl MemoryFs {
                 size,
                 modified,
                 is_dir: false,
+                content_hash: None,
             },
         );
 
@@ -388,8 +674,33 @@ impl FileOpener for MemoryFs {
                     size: file.size,
                     modified: file.modified,
                     is_dir: file.is_dir,
+                    content_hash: file.content_hash.clone(),
                 })
                 .ok_or_else(|| Error::new(ErrorKind::NotFound, "Not found")),
         )
     }
+
+    fn read_dir(&self, path: &Path) -> ReadDirFuture {
+        match self.files.get(path) {
+            Some(dir) if dir.is_dir => {}
+            Some(_) => return Box::pin(ready(Err(Error::other("Not a directory")))),
+            None => return Box::pin(ready(Err(Error::new(ErrorKind::NotFound, "Not found")))),
+        }
+
+        // This is a linear scan, but listings are expected to be rare compared to `open`.
+        let entries = self
+            .files
+            .iter()
+            .filter(|(entry_path, _)| entry_path.parent() == Some(path))
+            .filter_map(|(entry_path, file)| {
+                Some(DirEntry {
+                    name: entry_path.file_name()?.to_os_string(),
+                    size: file.size,
+                    modified: file.modified,
+                    is_dir: file.is_dir,
+                })
+            })
+            .collect();
+        Box::pin(ready(Ok(entries)))
+    }
 }
@@ -0,0 +1,344 @@
+use std::{
+    cmp::min,
+    collections::HashMap,
+    fs::File as StdFile,
+    future::Future,
+    io::{Error, SeekFrom},
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{ready, Context, Poll},
+    time::{Duration, Instant, SystemTime},
+};
+
+use futures_util::future::BoxFuture;
+use hyper::body::Bytes;
+use tokio::{fs::File, io::AsyncSeek, task::JoinHandle};
+
+use super::{
+    FileAccess, FileOpener, FileWithMetadata, IntoFileAccess, ReadDirFuture, TokioFileOpener,
+    TOKIO_READ_BUF_SIZE,
+};
+
+/// Filesystem implementation that keeps recently used files open.
+///
+/// This wraps an opener that produces tokio `File`s, such as `TokioFileOpener`, and keeps a least
+/// recently used set of open handles, similar to the nginx `open_file_cache`. Handles are shared
+/// between requests, and read using positional reads, so requests don't interfere with each
+/// other.
+///
+/// Entries are used for a limited time, after which the file is opened again to check for changes.
+/// The cached handle is kept only if the size, modification time and, on Unix, the device and
+/// inode number are unchanged. Otherwise, it is replaced with the new handle. Until the check,
+/// requests see the file as it was when it was opened, so a file that is rewritten in place may be
+/// served with its old size. Note that on most systems, a file that is replaced or deleted remains
+/// readable through the open handle. Call `invalidate` or `clear` to pick up changes immediately.
+///
+/// Files that are not found are not cached, see `NegativeCache` for that.
+///
+/// ```rust
+/// use std::time::Duration;
+///
+/// use hyper_staticfile::vfs::{HandleCacheFs, TokioFileOpener};
+///
+/// let fs = HandleCacheFs::new(TokioFileOpener::new("public/"), 1024)
+///     .validity(Duration::from_secs(10));
+/// ```
+pub struct HandleCacheFs<O = TokioFileOpener> {
+    inner: O,
+    validity: Duration,
+    cache: Arc<Mutex<HandleCache>>,
+}
+
+impl<O: FileOpener<File = File>> HandleCacheFs<O> {
+    /// Create a new `HandleCacheFs`, keeping at most `capacity` files open.
+    ///
+    /// Entries are valid for one second by default. See `validity`.
+    pub fn new(inner: O, capacity: usize) -> Self {
+        Self {
+            inner,
+            validity: Duration::from_secs(1),
+            cache: Arc::new(Mutex::new(HandleCache {
+                capacity,
+                ..HandleCache::default()
+            })),
+        }
+    }
+
+    /// Set how long entries are used before the file is checked for changes.
+    pub fn validity(mut self, validity: Duration) -> Self {
+        self.validity = validity;
+        self
+    }
+
+    /// Close the handles of a path, and all paths below it.
+    pub fn invalidate(&self, path: &Path) {
+        self.cache
+            .lock()
+            .unwrap()
+            .entries
+            .retain(|entry, _| !entry.starts_with(path));
+    }
+
+    /// Close all handles.
+    pub fn clear(&self) {
+        self.cache.lock().unwrap().entries.clear();
+    }
+
+    /// Number of handles currently open in the cache.
+    pub fn len(&self) -> usize {
+        self.cache.lock().unwrap().entries.len()
+    }
+
+    /// Whether no handles are currently open in the cache.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<O: FileOpener<File = File>> FileOpener for HandleCacheFs<O> {
+    type File = SharedFile;
+    type Future = BoxFuture<'static, Result<FileWithMetadata<Self::File>, Error>>;
+
+    fn open(&self, path: &Path) -> Self::Future {
+        if let Some(file) = self.cache.lock().unwrap().get(path, self.validity) {
+            return Box::pin(async move { Ok(file) });
+        }
+
+        let future = self.inner.open(path);
+        let cache = self.cache.clone();
+        let path = path.to_path_buf();
+        Box::pin(async move {
+            let file = future.await?;
+            let id = file_id(&file.handle).await;
+
+            // A freshly opened file has no operations in flight, so this always succeeds.
+            let handle = file
+                .handle
+                .try_into_std()
+                .map_err(|_| Error::other("file has operations in flight"))?;
+            let file = FileWithMetadata {
+                handle: SharedFile {
+                    file: Arc::new(handle),
+                    size: file.size,
+                },
+                size: file.size,
+                modified: file.modified,
+                is_dir: file.is_dir,
+                content_hash: file.content_hash,
+            };
+
+            // If the file is unchanged, keep using the handle we already have.
+            Ok(cache.lock().unwrap().insert(path, file, id))
+        })
+    }
+
+    fn read_dir(&self, path: &Path) -> ReadDirFuture {
+        self.inner.read_dir(path)
+    }
+}
+
+/// An open file in the cache.
+struct HandleEntry {
+    file: Arc<StdFile>,
+    size: u64,
+    modified: Option<SystemTime>,
+    is_dir: bool,
+    content_hash: Option<Arc<str>>,
+    /// Device and inode number, on Unix.
+    id: Option<(u64, u64)>,
+    /// When the file was opened, or last found to be unchanged.
+    opened: Instant,
+    /// The tick at which the entry was last used.
+    last_used: u64,
+}
+
+/// Storage for `HandleCacheFs`, with least recently used eviction.
+#[derive(Default)]
+struct HandleCache {
+    entries: HashMap<PathBuf, HandleEntry>,
+    /// Maximum number of entries.
+    capacity: usize,
+    /// Counter used to track use of entries.
+    tick: u64,
+}
+
+impl HandleEntry {
+    fn to_file(&self) -> FileWithMetadata<SharedFile> {
+        FileWithMetadata {
+            handle: SharedFile {
+                file: self.file.clone(),
+                size: self.size,
+            },
+            size: self.size,
+            modified: self.modified,
+            is_dir: self.is_dir,
+            content_hash: self.content_hash.clone(),
+        }
+    }
+}
+
+impl HandleCache {
+    /// Get the entry for a path, unless it needs to be checked for changes.
+    fn get(&mut self, path: &Path, validity: Duration) -> Option<FileWithMetadata<SharedFile>> {
+        self.tick += 1;
+        let entry = self.entries.get_mut(path)?;
+        if entry.opened.elapsed() >= validity {
+            return None;
+        }
+        entry.last_used = self.tick;
+        Some(entry.to_file())
+    }
+
+    /// Add a freshly opened file, or keep the existing entry if the file is unchanged.
+    fn insert(
+        &mut self,
+        path: PathBuf,
+        file: FileWithMetadata<SharedFile>,
+        id: Option<(u64, u64)>,
+    ) -> FileWithMetadata<SharedFile> {
+        if self.capacity == 0 {
+            return file;
+        }
+
+        self.tick += 1;
+        if let Some(entry) = self.entries.get_mut(&path) {
+            if entry.size == file.size
+                && entry.modified == file.modified
+                && entry.is_dir == file.is_dir
+                && entry.id == id
+            {
+                entry.opened = Instant::now();
+                entry.last_used = self.tick;
+                return entry.to_file();
+            }
+        }
+
+        // This is a linear scan, but eviction only happens when a file was opened.
+        if !self.entries.contains_key(&path) && self.entries.len() >= self.capacity {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(path, _)| path.clone())
+                .unwrap();
+            self.entries.remove(&oldest);
+        }
+
+        let entry = HandleEntry {
+            file: file.handle.file.clone(),
+            size: file.size,
+            modified: file.modified,
+            is_dir: file.is_dir,
+            content_hash: file.content_hash.clone(),
+            id,
+            opened: Instant::now(),
+            last_used: self.tick,
+        };
+        self.entries.insert(path, entry);
+        file
+    }
+}
+
+/// The device and inode number of a file, to detect files that were replaced.
+#[cfg(unix)]
+async fn file_id(file: &File) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = file.metadata().await.ok()?;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+/// Files can't be identified on this platform, so only size and modification time are compared.
+#[cfg(not(unix))]
+async fn file_id(_file: &File) -> Option<(u64, u64)> {
+    None
+}
+
+/// File handle type produced by `HandleCacheFs`, shared with other requests.
+pub struct SharedFile {
+    file: Arc<StdFile>,
+    size: u64,
+}
+
+impl IntoFileAccess for SharedFile {
+    type Output = SharedFileAccess;
+
+    fn into_file_access(self) -> Self::Output {
+        SharedFileAccess {
+            file: self.file,
+            size: self.size,
+            pos: 0,
+            read: None,
+        }
+    }
+}
+
+/// Struct that reads a `SharedFile` using positional reads, to implement `FileAccess`.
+pub struct SharedFileAccess {
+    file: Arc<StdFile>,
+    size: u64,
+    pos: u64,
+    read: Option<JoinHandle<Result<Bytes, Error>>>,
+}
+
+impl AsyncSeek for SharedFileAccess {
+    fn start_seek(mut self: Pin<&mut Self>, position: SeekFrom) -> std::io::Result<()> {
+        if self.read.is_some() {
+            return Err(Error::other("other file operation is pending"));
+        }
+        let pos = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.size.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+        };
+        self.pos = pos.ok_or_else(|| Error::other("invalid seek to a negative position"))?;
+        Ok(())
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<u64>> {
+        Poll::Ready(Ok(self.pos))
+    }
+}
+
+impl FileAccess for SharedFileAccess {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        len: usize,
+    ) -> Poll<Result<Bytes, Error>> {
+        let read = match self.read {
+            Some(ref mut read) => read,
+            None => {
+                let file = self.file.clone();
+                let pos = self.pos;
+                let len = min(len, TOKIO_READ_BUF_SIZE);
+                self.read.insert(tokio::task::spawn_blocking(move || {
+                    let mut buf = vec![0; len];
+                    let n = read_at(&file, &mut buf, pos)?;
+                    buf.truncate(n);
+                    Ok(buf.into())
+                }))
+            }
+        };
+
+        let res = ready!(Pin::new(read).poll(cx)).unwrap_or_else(|err| Err(Error::other(err)));
+        self.read = None;
+        if let Ok(ref bytes) = res {
+            self.pos += bytes.len() as u64;
+        }
+        Poll::Ready(res)
+    }
+}
+
+/// Read from a position in the file, without using the file cursor.
+#[cfg(unix)]
+fn read_at(file: &StdFile, buf: &mut [u8], pos: u64) -> Result<usize, Error> {
+    std::os::unix::fs::FileExt::read_at(file, buf, pos)
+}
+
+/// Read from a position in the file. This moves the file cursor, but we never use it.
+#[cfg(windows)]
+fn read_at(file: &StdFile, buf: &mut [u8], pos: u64) -> Result<usize, Error> {
+    std::os::windows::fs::FileExt::seek_read(file, buf, pos)
+}
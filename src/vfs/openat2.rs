@@ -0,0 +1,247 @@
+use std::{
+    ffi::{CStr, CString, OsStr, OsString},
+    fs::{File as StdFile, Metadata},
+    io::{Error, ErrorKind},
+    mem,
+    os::unix::{
+        ffi::{OsStrExt, OsStringExt},
+        fs::OpenOptionsExt,
+        io::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd, RawFd},
+    },
+    path::Path,
+    sync::Arc,
+};
+
+use tokio::{fs::File, task::spawn_blocking};
+
+use super::{
+    DirEntry, FileOpener, FileWithMetadata, ReadDirFuture, TokioFileFuture, TokioReadDirFuture,
+};
+
+/// Filesystem implementation that confines lookups to the root using Linux `openat2`.
+///
+/// This opener keeps a handle to the root directory, and opens every path relative to it with
+/// `RESOLVE_BENEATH | RESOLVE_NO_MAGICLINKS`. Unlike the path checks in `TokioFileOpener`, the
+/// kernel enforces that resolution never leaves the root, even if the tree changes concurrently.
+/// Symlinks are followed as long as they stay beneath the root.
+///
+/// Paths that would escape the root result in a `PermissionDenied` error.
+///
+/// This type is only available on Linux, and requires Linux 5.6 or later.
+pub struct Openat2FileOpener {
+    root: Arc<OwnedFd>,
+
+    /// Whether to refuse all symlinks, using `RESOLVE_NO_SYMLINKS`. Defaults to `false`.
+    pub no_symlinks: bool,
+}
+
+impl Openat2FileOpener {
+    /// Create a new `Openat2FileOpener` for the given root path.
+    ///
+    /// The path may be absolute or relative, and is opened immediately. Returns an error of kind
+    /// `ErrorKind::Unsupported` if the kernel does not support `openat2`, so callers can fall back
+    /// to `TokioFileOpener`.
+    pub fn new(root: impl AsRef<Path>) -> Result<Self, Error> {
+        let root: OwnedFd = std::fs::OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_DIRECTORY | libc::O_CLOEXEC)
+            .open(root)?
+            .into();
+
+        // Probe for support by opening the root itself.
+        match openat2(
+            root.as_raw_fd(),
+            Path::new(""),
+            libc::O_PATH,
+            RESOLVE_DEFAULT,
+        ) {
+            Ok(_) => {}
+            Err(err) if matches!(err.raw_os_error(), Some(libc::ENOSYS) | Some(libc::EPERM)) => {
+                return Err(Error::new(
+                    ErrorKind::Unsupported,
+                    "openat2 is not supported by the kernel",
+                ));
+            }
+            Err(err) => return Err(err),
+        }
+
+        Ok(Self {
+            root: Arc::new(root),
+            no_symlinks: false,
+        })
+    }
+
+    fn resolve_flags(&self) -> u64 {
+        if self.no_symlinks {
+            RESOLVE_DEFAULT | libc::RESOLVE_NO_SYMLINKS
+        } else {
+            RESOLVE_DEFAULT
+        }
+    }
+}
+
+const RESOLVE_DEFAULT: u64 = libc::RESOLVE_BENEATH | libc::RESOLVE_NO_MAGICLINKS;
+
+/// Number of times to retry `openat2` when the kernel asks us to, before giving up.
+const MAX_RETRIES: usize = 16;
+
+/// Open a path relative to a directory handle using `openat2`.
+///
+/// An empty path opens the directory itself.
+fn openat2(dirfd: RawFd, path: &Path, flags: libc::c_int, resolve: u64) -> Result<OwnedFd, Error> {
+    let path = if path.as_os_str().is_empty() {
+        OsStr::new(".")
+    } else {
+        path.as_os_str()
+    };
+    let path = CString::new(path.as_bytes())
+        .map_err(|_| Error::new(ErrorKind::InvalidInput, "path contains a nul byte"))?;
+
+    // `open_how` is non-exhaustive, so start from a zeroed value.
+    let mut how: libc::open_how = unsafe { mem::zeroed() };
+    how.flags = (flags | libc::O_CLOEXEC) as u64;
+    how.resolve = resolve;
+
+    let mut retries = 0;
+    loop {
+        let res = unsafe {
+            libc::syscall(
+                libc::SYS_openat2,
+                dirfd,
+                path.as_ptr(),
+                &how as *const libc::open_how,
+                mem::size_of::<libc::open_how>(),
+            )
+        };
+        if res >= 0 {
+            return Ok(unsafe { OwnedFd::from_raw_fd(res as RawFd) });
+        }
+
+        let err = Error::last_os_error();
+        match err.raw_os_error() {
+            // The kernel may ask us to retry if the tree changed during a `RESOLVE_BENEATH` lookup.
+            // If the tree keeps changing, give up rather than spinning.
+            Some(libc::EINTR) | Some(libc::EAGAIN) if retries < MAX_RETRIES => {
+                retries += 1;
+                continue;
+            }
+            // Attempts to escape the root, or to follow a refused symlink.
+            Some(libc::EXDEV) | Some(libc::ELOOP) => {
+                return Err(Error::new(
+                    ErrorKind::PermissionDenied,
+                    "path resolution refused",
+                ));
+            }
+            _ => return Err(err),
+        }
+    }
+}
+
+/// Directory stream that is closed on drop.
+struct Dir(*mut libc::DIR);
+
+impl Dir {
+    fn from_fd(fd: OwnedFd) -> Result<Self, Error> {
+        let fd = fd.into_raw_fd();
+        let dir = unsafe { libc::fdopendir(fd) };
+        if dir.is_null() {
+            let err = Error::last_os_error();
+            unsafe { libc::close(fd) };
+            return Err(err);
+        }
+        Ok(Self(dir))
+    }
+
+    fn names(&mut self) -> Result<Vec<OsString>, Error> {
+        let mut names = Vec::new();
+        loop {
+            unsafe { *libc::__errno_location() = 0 };
+            let entry = unsafe { libc::readdir(self.0) };
+            if entry.is_null() {
+                return match Error::last_os_error().raw_os_error() {
+                    Some(0) | None => Ok(names),
+                    _ => Err(Error::last_os_error()),
+                };
+            }
+
+            let name = unsafe { CStr::from_ptr((*entry).d_name.as_ptr()) }.to_bytes();
+            if name != b"." && name != b".." {
+                names.push(OsString::from_vec(name.to_vec()));
+            }
+        }
+    }
+}
+
+impl Drop for Dir {
+    fn drop(&mut self) {
+        unsafe { libc::closedir(self.0) };
+    }
+}
+
+fn metadata_of(fd: OwnedFd) -> Result<Metadata, Error> {
+    StdFile::from(fd).metadata()
+}
+
+impl FileOpener for Openat2FileOpener {
+    type File = File;
+    type Future = TokioFileFuture;
+
+    fn open(&self, path: &Path) -> Self::Future {
+        let root = self.root.clone();
+        let path = path.to_path_buf();
+        let resolve = self.resolve_flags();
+
+        let inner = spawn_blocking(move || {
+            let handle = StdFile::from(openat2(root.as_raw_fd(), &path, libc::O_RDONLY, resolve)?);
+            let metadata = handle.metadata()?;
+            Ok(FileWithMetadata {
+                handle: File::from_std(handle),
+                size: metadata.len(),
+                modified: metadata.modified().ok(),
+                is_dir: metadata.is_dir(),
+                content_hash: None,
+            })
+        });
+
+        TokioFileFuture { inner }
+    }
+
+    fn read_dir(&self, path: &Path) -> ReadDirFuture {
+        let root = self.root.clone();
+        let path = path.to_path_buf();
+        let resolve = self.resolve_flags();
+
+        let inner = spawn_blocking(move || {
+            let fd = openat2(
+                root.as_raw_fd(),
+                &path,
+                libc::O_RDONLY | libc::O_DIRECTORY,
+                resolve,
+            )?;
+            let mut dir = Dir::from_fd(fd)?;
+
+            let mut entries = Vec::new();
+            for name in dir.names()? {
+                // Stat each entry through `openat2` relative to the root, so symlinks are subject
+                // to the same restrictions as in `open`. Entries that can't be resolved are left
+                // out of the listing.
+                let metadata =
+                    match openat2(root.as_raw_fd(), &path.join(&name), libc::O_PATH, resolve)
+                        .and_then(metadata_of)
+                    {
+                        Ok(metadata) => metadata,
+                        Err(_) => continue,
+                    };
+                entries.push(DirEntry {
+                    name,
+                    size: metadata.len(),
+                    modified: metadata.modified().ok(),
+                    is_dir: metadata.is_dir(),
+                });
+            }
+            Ok(entries)
+        });
+
+        Box::pin(TokioReadDirFuture { inner })
+    }
+}
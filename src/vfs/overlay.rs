@@ -0,0 +1,167 @@
+use std::{
+    collections::HashSet,
+    io::{Error, ErrorKind},
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use futures_util::future::BoxFuture;
+use hyper::body::Bytes;
+
+use super::{FileAccess, FileOpener, FileWithMetadata, IntoFileAccess, ReadDirFuture};
+
+/// Filesystem implementation that layers several openers in priority order.
+///
+/// Lookups try each layer in turn, and return the first hit. A `NotFound` error from a layer
+/// falls through to the next layer, while any other error is returned immediately. Directory
+/// listings merge the entries of all layers that have the directory, with entries from higher
+/// priority layers taking precedence.
+///
+/// Layers may be of different types, so `TokioFileOpener` and `MemoryFs` can be combined freely:
+///
+/// ```rust
+/// use hyper_staticfile::vfs::{MemoryFs, OverlayFs, TokioFileOpener};
+///
+/// let mut overrides = MemoryFs::default();
+/// overrides.add("theme.css", "body { color: red; }".into(), None);
+///
+/// let mut fs = OverlayFs::new();
+/// fs.layer(overrides).layer(TokioFileOpener::new("theme/"));
+/// ```
+#[derive(Default)]
+pub struct OverlayFs {
+    layers: Vec<Arc<dyn DynFileOpener>>,
+}
+
+impl OverlayFs {
+    /// Create a new `OverlayFs` without any layers.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a layer below all existing layers.
+    pub fn layer(&mut self, opener: impl FileOpener) -> &mut Self {
+        self.layers.push(Arc::new(opener));
+        self
+    }
+}
+
+impl FileOpener for OverlayFs {
+    type File = OverlayFile;
+    type Future = BoxFuture<'static, Result<FileWithMetadata<OverlayFile>, Error>>;
+
+    fn open(&self, path: &Path) -> Self::Future {
+        let layers = self.layers.clone();
+        let path = path.to_path_buf();
+        Box::pin(async move {
+            for layer in layers {
+                match layer.open(path.clone()).await {
+                    Err(err) if err.kind() == ErrorKind::NotFound => continue,
+                    res => return res,
+                }
+            }
+            Err(Error::new(ErrorKind::NotFound, "Not found"))
+        })
+    }
+
+    fn read_dir(&self, path: &Path) -> ReadDirFuture {
+        let layers = self.layers.clone();
+        let path = path.to_path_buf();
+        Box::pin(async move {
+            let mut found = false;
+            let mut names = HashSet::new();
+            let mut entries = Vec::new();
+            for layer in layers {
+                let layer_entries = match layer.read_dir(path.clone()).await {
+                    Ok(layer_entries) => layer_entries,
+                    Err(err)
+                        if matches!(err.kind(), ErrorKind::NotFound | ErrorKind::Unsupported) =>
+                    {
+                        continue
+                    }
+                    Err(err) => return Err(err),
+                };
+                found = true;
+                for entry in layer_entries {
+                    if names.insert(entry.name.clone()) {
+                        entries.push(entry);
+                    }
+                }
+            }
+            if found {
+                Ok(entries)
+            } else {
+                Err(Error::new(ErrorKind::NotFound, "Not found"))
+            }
+        })
+    }
+}
+
+/// Object-safe version of `FileOpener`, used to store layers of different types.
+trait DynFileOpener: Send + Sync + 'static {
+    fn open(
+        &self,
+        path: PathBuf,
+    ) -> BoxFuture<'static, Result<FileWithMetadata<OverlayFile>, Error>>;
+
+    fn read_dir(&self, path: PathBuf) -> ReadDirFuture;
+}
+
+impl<O: FileOpener> DynFileOpener for O {
+    fn open(
+        &self,
+        path: PathBuf,
+    ) -> BoxFuture<'static, Result<FileWithMetadata<OverlayFile>, Error>> {
+        let future = FileOpener::open(self, &path);
+        Box::pin(async move {
+            let file = future.await?;
+            Ok(FileWithMetadata {
+                handle: OverlayFile(Box::new(file.handle)),
+                size: file.size,
+                modified: file.modified,
+                is_dir: file.is_dir,
+                content_hash: file.content_hash,
+            })
+        })
+    }
+
+    fn read_dir(&self, path: PathBuf) -> ReadDirFuture {
+        FileOpener::read_dir(self, &path)
+    }
+}
+
+/// Object-safe version of `IntoFileAccess`.
+trait DynIntoFileAccess: Send + Unpin + 'static {
+    fn into_boxed(self: Box<Self>) -> Box<dyn FileAccess>;
+}
+
+impl<F: IntoFileAccess> DynIntoFileAccess for F {
+    fn into_boxed(self: Box<Self>) -> Box<dyn FileAccess> {
+        Box::new((*self).into_file_access())
+    }
+}
+
+/// File handle type produced by `OverlayFs`.
+///
+/// This wraps the file handle of whichever layer the file was found in.
+pub struct OverlayFile(Box<dyn DynIntoFileAccess>);
+
+impl IntoFileAccess for OverlayFile {
+    type Output = Box<dyn FileAccess>;
+
+    fn into_file_access(self) -> Self::Output {
+        self.0.into_boxed()
+    }
+}
+
+impl FileAccess for Box<dyn FileAccess> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        len: usize,
+    ) -> Poll<Result<Bytes, Error>> {
+        Pin::new(&mut **self).poll_read(cx, len)
+    }
+}
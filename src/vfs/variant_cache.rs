@@ -0,0 +1,292 @@
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::OsString,
+    io::{Cursor, Error, ErrorKind},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use futures_util::{future::BoxFuture, TryStreamExt};
+use hyper::body::Bytes;
+use mime_guess::MimeGuess;
+use tokio::task::spawn_blocking;
+
+use super::{FileAccess, FileOpener, FileWithMetadata, IntoFileAccess, ReadDirFuture};
+use crate::{
+    compression::is_available,
+    util::{compress_slice, FileBytesStream},
+    Compression, Encoding,
+};
+
+/// Filesystem implementation that lazily generates and caches compressed variants of files.
+///
+/// This wraps another opener, and answers the lookups `Resolver` does for pre-encoded files, such
+/// as `style.css.gz`. If the inner opener has no such file, the original is compressed in the
+/// background, and the result is stored in memory. Until then, the lookup fails as usual, so the
+/// first requests are served without the variant. Once cached, variants are served like regular
+/// pre-encoded files, with a `Content-Length` and support for range requests.
+///
+/// Entries are keyed by path, size, modification time and encoding, so changes to the original
+/// file result in a new variant. Files without a modification time are never cached. A variant is
+/// only served if it is smaller than the original, like with `MemoryFs::precompress`. Otherwise,
+/// only the outcome is remembered, so the file is not compressed again. The cache is bounded by the
+/// total size of the variants, and evicts the least recently used entries.
+///
+/// The `Compression` settings determine which files are compressed, and with which encodings. Only
+/// encodings the resolver allows are ever looked up, so `Resolver::allowed_encodings` must include
+/// them as well. Because compression happens once per file, a high level is usually a good choice:
+///
+/// ```rust
+/// use hyper_staticfile::{
+///     vfs::{TokioFileOpener, VariantCacheFs},
+///     Compression, CompressionLevel,
+/// };
+///
+/// let compression = Compression::new().level(CompressionLevel::Best);
+/// let fs = VariantCacheFs::new(TokioFileOpener::new("public/"), compression, 64 * 1024 * 1024);
+/// ```
+pub struct VariantCacheFs<O> {
+    inner: Arc<O>,
+    compression: Arc<Compression>,
+    cache: Arc<Mutex<VariantCache>>,
+}
+
+impl<O: FileOpener> VariantCacheFs<O> {
+    /// Create a new `VariantCacheFs`, caching at most `capacity` bytes of variants.
+    pub fn new(inner: O, compression: Compression, capacity: u64) -> Self {
+        Self {
+            inner: Arc::new(inner),
+            compression: Arc::new(compression),
+            cache: Arc::new(Mutex::new(VariantCache {
+                capacity,
+                ..VariantCache::default()
+            })),
+        }
+    }
+
+    /// Total size in bytes of the variants currently cached.
+    pub fn cached_size(&self) -> u64 {
+        self.cache.lock().unwrap().size
+    }
+}
+
+impl<O: FileOpener> FileOpener for VariantCacheFs<O> {
+    type File = VariantCacheFile<O::File>;
+    type Future = BoxFuture<'static, Result<FileWithMetadata<Self::File>, Error>>;
+
+    fn open(&self, path: &Path) -> Self::Future {
+        let future = self.inner.open(path);
+        let inner = self.inner.clone();
+        let compression = self.compression.clone();
+        let cache = self.cache.clone();
+        let path = path.to_path_buf();
+        Box::pin(async move {
+            let err = match future.await {
+                Ok(file) => {
+                    return Ok(FileWithMetadata {
+                        handle: VariantCacheFile::Original(file.handle),
+                        size: file.size,
+                        modified: file.modified,
+                        is_dir: file.is_dir,
+                        content_hash: file.content_hash,
+                    })
+                }
+                Err(err) if err.kind() == ErrorKind::NotFound => err,
+                Err(err) => return Err(err),
+            };
+
+            // Check whether this is a lookup for a variant of a file we should compress.
+            let (base, encoding) = match split_variant(&path, &compression) {
+                Some(res) => res,
+                None => return Err(err),
+            };
+            let file = match inner.open(&base).await {
+                Ok(file) if !file.is_dir && file.size >= compression.min_size => file,
+                _ => return Err(err),
+            };
+            let modified = match file.modified {
+                Some(modified) => modified,
+                None => return Err(err),
+            };
+            let key = VariantKey {
+                path: base,
+                size: file.size,
+                modified,
+                encoding: encoding.clone(),
+            };
+
+            let mut guard = cache.lock().unwrap();
+            if let Some(data) = guard.get(&key) {
+                // The variant was not smaller than the original.
+                let data = match data {
+                    Some(data) => data,
+                    None => return Err(err),
+                };
+                return Ok(FileWithMetadata {
+                    size: data.len() as u64,
+                    handle: VariantCacheFile::Variant(Cursor::new(data)),
+                    modified: Some(modified),
+                    is_dir: false,
+                    content_hash: None,
+                });
+            }
+
+            // Generate the variant in the background, unless another request already started. The
+            // file is read on the runtime, but compression happens on a blocking thread, because
+            // it can take a long time at high levels.
+            if guard.pending.insert(key.clone()) {
+                drop(guard);
+                let pending = PendingGuard {
+                    cache: cache.clone(),
+                    key: key.clone(),
+                };
+                let level = compression.level;
+                tokio::spawn(async move {
+                    let _pending = pending;
+                    let file_access = file.handle.into_file_access();
+                    let chunks: Vec<Bytes> =
+                        match FileBytesStream::new_with_limit(file_access, file.size)
+                            .try_collect()
+                            .await
+                        {
+                            Ok(chunks) => chunks,
+                            Err(_) => return,
+                        };
+                    let data = chunks.concat();
+                    let res = spawn_blocking(move || compress_slice(&data, encoding, level)).await;
+                    if let Ok(Ok(data)) = res {
+                        let data = Some(data).filter(|data| (data.len() as u64) < key.size);
+                        cache.lock().unwrap().insert(key, data);
+                    }
+                });
+            }
+            Err(err)
+        })
+    }
+
+    fn read_dir(&self, path: &Path) -> ReadDirFuture {
+        self.inner.read_dir(path)
+    }
+}
+
+/// Split a path into the path of the original file and the encoding of the variant.
+fn split_variant(path: &Path, compression: &Compression) -> Option<(PathBuf, Encoding)> {
+    let name = path.file_name()?.to_str()?;
+    let (stem, encoding) = [Encoding::Gzip, Encoding::Br, Encoding::Zstd]
+        .iter()
+        .find_map(|enc| Some((name.strip_suffix(enc.suffix())?, enc.clone())))?;
+    if stem.is_empty() || !compression.encodings.accepts(&encoding) || !is_available(&encoding) {
+        return None;
+    }
+    let base = path.with_file_name(OsString::from(stem));
+    let content_type = MimeGuess::from_path(&base).first()?;
+    if !compression.applies_to(content_type.as_ref()) {
+        return None;
+    }
+    Some((base, encoding))
+}
+
+/// Cache key of a variant.
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct VariantKey {
+    path: PathBuf,
+    size: u64,
+    modified: SystemTime,
+    encoding: Encoding,
+}
+
+/// Removes a variant from the pending set when generating it finishes, fails, or panics.
+struct PendingGuard {
+    cache: Arc<Mutex<VariantCache>>,
+    key: VariantKey,
+}
+
+impl Drop for PendingGuard {
+    fn drop(&mut self) {
+        // Don't panic while already panicking.
+        if let Ok(mut guard) = self.cache.lock() {
+            guard.pending.remove(&self.key);
+        }
+    }
+}
+
+/// Storage for `VariantCacheFs`, with least recently used eviction.
+#[derive(Default)]
+struct VariantCache {
+    /// Cached variants, with the tick at which they were last used. Variants that were not smaller
+    /// than the original are recorded as `None`.
+    entries: HashMap<VariantKey, (Option<Bytes>, u64)>,
+    /// Variants currently being generated.
+    pending: HashSet<VariantKey>,
+    /// Total size in bytes of the cached variants. (See `VariantCache::cost`.)
+    size: u64,
+    /// Maximum total size in bytes of the cached variants.
+    capacity: u64,
+    /// Counter used to track use of entries.
+    tick: u64,
+}
+
+impl VariantCache {
+    /// Size in bytes an entry is accounted for. Entries without a variant are charged for their
+    /// path, so they are also subject to eviction.
+    fn cost(key: &VariantKey, data: &Option<Bytes>) -> u64 {
+        match data {
+            Some(data) => data.len() as u64,
+            None => key.path.as_os_str().len() as u64,
+        }
+    }
+
+    fn get(&mut self, key: &VariantKey) -> Option<Option<Bytes>> {
+        self.tick += 1;
+        let tick = self.tick;
+        self.entries.get_mut(key).map(|(data, last_used)| {
+            *last_used = tick;
+            data.clone()
+        })
+    }
+
+    fn insert(&mut self, key: VariantKey, data: Option<Bytes>) {
+        let len = Self::cost(&key, &data);
+        if len > self.capacity {
+            return;
+        }
+
+        // This is a linear scan, but eviction only happens when a new variant was generated.
+        while self.size + len > self.capacity {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, (_, last_used))| *last_used)
+                .map(|(key, _)| key.clone())
+                .unwrap();
+            let (data, _) = self.entries.remove(&oldest).unwrap();
+            self.size -= Self::cost(&oldest, &data);
+        }
+
+        self.tick += 1;
+        self.size += len;
+        if let Some((data, _)) = self.entries.insert(key.clone(), (data, self.tick)) {
+            self.size -= Self::cost(&key, &data);
+        }
+    }
+}
+
+/// File handle type produced by `VariantCacheFs`.
+pub enum VariantCacheFile<F> {
+    /// A file from the inner opener.
+    Original(F),
+    /// A cached variant.
+    Variant(Cursor<Bytes>),
+}
+
+impl<F: IntoFileAccess> IntoFileAccess for VariantCacheFile<F> {
+    type Output = Box<dyn FileAccess>;
+
+    fn into_file_access(self) -> Self::Output {
+        match self {
+            VariantCacheFile::Original(file) => Box::new(file.into_file_access()),
+            VariantCacheFile::Variant(cursor) => Box::new(cursor),
+        }
+    }
+}
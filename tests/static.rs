@@ -4,6 +4,7 @@ use std::{
     io::{Cursor, Error as IoError, Read, Write},
     process::Command,
     str,
+    sync::Arc,
     time::{Duration, SystemTime},
 };
 
@@ -12,8 +13,12 @@ use http_body_util::BodyExt;
 use httpdate::fmt_http_date;
 use hyper::body::Buf;
 use hyper_staticfile::{
-    vfs::{FileAccess, MemoryFs},
-    AcceptEncoding, Body, Encoding, Static,
+    util::Glob,
+    vfs::{
+        FileAccess, FileOpener, HandleCacheFs, MemoryFs, OverlayFs, SymlinkPolicy, TokioFileOpener,
+    },
+    AcceptEncoding, Body, CacheControl, CachePolicy, DotfilePolicy, Encoding, Fallback,
+    NegativeCache, RedirectStatus, ResolvedFile, Static, Validators,
 };
 use tempfile::TempDir;
 
@@ -108,6 +113,34 @@ async fn serves_default_file_from_empty_root_path() {
     assert_eq!(read_body(res).await, "this is index");
 }
 
+#[tokio::test]
+async fn serves_configured_index_files_in_order() {
+    let mut harness = Harness::new(vec![
+        ("legacy/index.htm", "this is index.htm"),
+        ("legacy/default.html", "this is default.html"),
+        ("legacy/index.htm.gz", "fake gzip compression"),
+    ]);
+    harness
+        .static_
+        .index_files(["index.html", "index.htm", "default.html"]);
+
+    let res = harness.get("/legacy/").await.unwrap();
+    assert_eq!(read_body(res).await, "this is index.htm");
+
+    // Pre-encoded variants apply to whichever index file was found.
+    let req = Request::builder()
+        .uri("/legacy/")
+        .header(header::ACCEPT_ENCODING, "gzip")
+        .body(())
+        .expect("unable to build request");
+    let res = harness.request(req).await.unwrap();
+    assert_eq!(
+        res.headers().get(header::CONTENT_ENCODING),
+        Some(&Encoding::Gzip.to_header_value())
+    );
+    assert_eq!(read_body(res).await, "fake gzip compression");
+}
+
 #[tokio::test]
 async fn returns_404_if_file_not_found() {
     let harness = Harness::new(vec![]);
@@ -116,6 +149,127 @@ async fn returns_404_if_file_not_found() {
     assert_eq!(res.status(), StatusCode::NOT_FOUND);
 }
 
+#[tokio::test]
+async fn serves_fallback_document() {
+    let mut harness = Harness::new(vec![
+        ("index.html", "this is index"),
+        ("index.html.gz", "fake gzip compression"),
+        ("app.js", "this is app"),
+    ]);
+    harness
+        .static_
+        .fallback(Some(Fallback::new("/index.html").exclude("/api/**")));
+
+    let res = harness.get("/some/client/route").await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(read_body(res).await, "this is index");
+
+    // Existing files are served as usual.
+    let res = harness.get("/app.js").await.unwrap();
+    assert_eq!(read_body(res).await, "this is app");
+
+    // Missing assets and excluded paths are still a 404.
+    let res = harness.get("/missing.js").await.unwrap();
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    let res = harness.get("/api/users").await.unwrap();
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+    // The fallback document honors `Accept-Encoding`.
+    let req = Request::builder()
+        .uri("/some/client/route")
+        .header(header::ACCEPT_ENCODING, "gzip")
+        .body(())
+        .expect("unable to build request");
+    let res = harness.request(req).await.unwrap();
+    assert_eq!(
+        res.headers().get(header::CONTENT_ENCODING),
+        Some(&Encoding::Gzip.to_header_value())
+    );
+    assert_eq!(read_body(res).await, "fake gzip compression");
+}
+
+#[tokio::test]
+async fn fallback_include_overrides_extensions() {
+    let mut harness = Harness::new(vec![("index.html", "this is index")]);
+    harness
+        .static_
+        .fallback(Some(Fallback::new("index.html").include("/docs/*.html")));
+
+    let res = harness.get("/docs/page.html").await.unwrap();
+    assert_eq!(read_body(res).await, "this is index");
+
+    let res = harness.get("/page.html").await.unwrap();
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn matches_glob_patterns() {
+    let glob = Glob::new("/assets/*-[0-9a-f][0-9a-f].js");
+    assert!(glob.is_match("/assets/app-3e.js"));
+    assert!(!glob.is_match("/assets/app-3g.js"));
+    assert!(!glob.is_match("/assets/nested/app-3e.js"));
+
+    let glob = Glob::new("[!.]*.map");
+    assert!(glob.is_match("/js/app.js.map"));
+    assert!(!glob.is_match("/js/.app.map"));
+
+    let glob = Glob::new("/api/**/users");
+    assert!(glob.is_match("/api/users"));
+    assert!(glob.is_match("/api/v1/admin/users"));
+    assert!(!glob.is_match("/api/v1/admin"));
+
+    // Many wildcards against a long path must not backtrack exponentially.
+    let glob = Glob::new("/**a**a**a**a**a**a**a**a**b");
+    let path = format!("/{}", "a".repeat(10_000));
+    assert!(!glob.is_match(&path));
+    let glob = Glob::new("*a*a*a*a*a*a*a*a*b");
+    assert!(!glob.is_match(&path));
+}
+
+#[tokio::test]
+async fn serves_error_documents() {
+    let mut harness = Harness::new(vec![
+        ("404.html", "this is 404"),
+        ("404.html.gz", "fake gzip compression"),
+    ]);
+    harness
+        .static_
+        .error_document(StatusCode::NOT_FOUND, "/404.html")
+        .error_document(StatusCode::BAD_REQUEST, "/400.html");
+
+    let res = harness.get("/missing.html").await.unwrap();
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    assert_eq!(
+        res.headers().get(header::CONTENT_TYPE).unwrap(),
+        "text/html"
+    );
+    assert!(res.headers().get(header::ETAG).is_none());
+    assert_eq!(read_body(res).await, "this is 404");
+
+    let req = Request::builder()
+        .uri("/missing.html")
+        .header(header::ACCEPT_ENCODING, "gzip")
+        .body(())
+        .expect("unable to build request");
+    let res = harness.request(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    assert_eq!(
+        res.headers().get(header::CONTENT_ENCODING),
+        Some(&Encoding::Gzip.to_header_value())
+    );
+    assert_eq!(read_body(res).await, "fake gzip compression");
+
+    // The configured 400 document is missing, so the body is empty.
+    let req = Request::builder()
+        .method("POST")
+        .uri("/404.html")
+        .body(())
+        .expect("unable to build request");
+    let res = harness.request(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(read_body(res).await, "");
+}
+
 #[tokio::test]
 async fn redirects_if_trailing_slash_is_missing() {
     let harness = Harness::new(vec![("foo/bar/index.html", "this is index")]);
@@ -127,6 +281,106 @@ async fn redirects_if_trailing_slash_is_missing() {
     assert_eq!(url, "/foo/bar/");
 }
 
+#[tokio::test]
+async fn probes_try_suffixes() {
+    let mut harness = Harness::new(vec![
+        ("about.html", "this is about"),
+        ("blog.html", "this is blog"),
+        ("blog/index.html", "this is blog index"),
+        ("docs/index.html", "this is docs index"),
+    ]);
+    harness.static_.try_suffixes([".html"]);
+
+    let res = harness.get("/about").await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(
+        res.headers().get(header::CONTENT_TYPE).unwrap(),
+        "text/html"
+    );
+    assert_eq!(read_body(res).await, "this is about");
+
+    // Suffixes take precedence over a directory of the same name.
+    let res = harness.get("/blog").await.unwrap();
+    assert_eq!(read_body(res).await, "this is blog");
+
+    // Directories without a matching file are still redirected.
+    let res = harness.get("/docs").await.unwrap();
+    assert_eq!(res.status(), StatusCode::MOVED_PERMANENTLY);
+
+    let res = harness.get("/missing").await.unwrap();
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn redirects_to_canonical_path() {
+    let mut harness = Harness::new(vec![("about.html", "this is about")]);
+    harness
+        .static_
+        .try_suffixes([".html"])
+        .canonical_redirect(true);
+
+    let res = harness.get("/about.html?x=1").await.unwrap();
+    assert_eq!(res.status(), StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(res.headers().get(header::LOCATION).unwrap(), "/about?x=1");
+
+    let res = harness.get("/about").await.unwrap();
+    assert_eq!(read_body(res).await, "this is about");
+}
+
+#[cfg(target_family = "unix")]
+#[tokio::test]
+async fn percent_encodes_canonical_redirects() {
+    let mut harness = Harness::new(vec![
+        ("what?.html", "this is what"),
+        ("50%.html", "this is fifty percent"),
+        ("a b.html", "this is a b"),
+    ]);
+    harness
+        .static_
+        .try_suffixes([".html"])
+        .canonical_redirect(true);
+
+    for (path, location) in [
+        ("/what%3F.html", "/what%3F"),
+        ("/50%25.html", "/50%25"),
+        ("/a%20b.html", "/a%20b"),
+    ]
+    .iter()
+    {
+        let res = harness.get(path).await.unwrap();
+        assert_eq!(res.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(res.headers().get(header::LOCATION).unwrap(), location);
+    }
+
+    let res = harness.get("/what%3F").await.unwrap();
+    assert_eq!(read_body(res).await, "this is what");
+}
+
+#[tokio::test]
+async fn configures_trailing_slash_redirects() {
+    let mut harness = Harness::new(vec![
+        ("foo/bar/index.html", "this is index"),
+        ("file1.html", "this is file1"),
+    ]);
+    harness
+        .static_
+        .redirect_status(RedirectStatus::TemporaryRedirect)
+        .strip_trailing_slash(true);
+
+    let res = harness.get("/foo/bar").await.unwrap();
+    assert_eq!(res.status(), StatusCode::TEMPORARY_REDIRECT);
+    assert_eq!(res.headers().get(header::LOCATION).unwrap(), "/foo/bar/");
+
+    let res = harness.get("/file1.html/").await.unwrap();
+    assert_eq!(res.status(), StatusCode::TEMPORARY_REDIRECT);
+    assert_eq!(res.headers().get(header::LOCATION).unwrap(), "/file1.html");
+
+    harness.static_.redirect_directories(false);
+    let res = harness.get("/foo/bar").await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(read_body(res).await, "this is index");
+}
+
 #[tokio::test]
 async fn redirects_to_sanitized_path() {
     let harness = Harness::new(vec![("foo.org/bar/index.html", "this is index")]);
@@ -184,6 +438,44 @@ async fn prevents_from_escaping_root() {
     assert_eq!(read_body(res).await, "this is file1");
 }
 
+#[tokio::test]
+async fn applies_dotfile_policy() {
+    let mut harness = Harness::new(vec![
+        (".env", "SECRET=1"),
+        (".git/index.html", "this is git"),
+        (".well-known/security.txt", "this is security.txt"),
+        ("file1.html", "this is file1"),
+        ("file1.html.gz", "fake gzip compression"),
+    ]);
+
+    // Dotfiles are served by default.
+    let res = harness.get("/.env").await.unwrap();
+    assert_eq!(read_body(res).await, "SECRET=1");
+
+    harness
+        .static_
+        .dotfiles(DotfilePolicy::Deny)
+        .autoindex(true);
+    let res = harness.get("/.env").await.unwrap();
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    let res = harness.get("/.git/").await.unwrap();
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    let res = harness.get("/.well-known/security.txt").await.unwrap();
+    assert_eq!(read_body(res).await, "this is security.txt");
+
+    // Dotfiles are hidden from listings.
+    let res = harness.get("/").await.unwrap();
+    let body = read_body(res).await;
+    assert!(!body.contains(".env") && !body.contains(".git"));
+    assert!(body.contains(".well-known/"));
+
+    harness.static_.dotfiles(DotfilePolicy::Ignore);
+    let res = harness.get("/.env").await.unwrap();
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    let res = harness.get("/.git/").await.unwrap();
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+}
+
 #[tokio::test]
 async fn sends_headers() {
     let harness = Harness::new(vec![("file1.html", "this is file1")]);
@@ -205,6 +497,66 @@ async fn sends_headers() {
     assert_eq!(read_body(res).await, "this is file1");
 }
 
+#[tokio::test]
+async fn selects_cache_control_policy() {
+    let mut harness = Harness::new(vec![
+        ("assets/app-1a2b3c.js", "console.log(1)"),
+        ("about.html", "about"),
+        ("docs/index.html", "docs"),
+        ("docs/index.html.gz", "fake gzip"),
+        ("logo.png", "fake png"),
+        ("notes.txt", "notes"),
+    ]);
+    harness.static_.cache_control(Some(
+        CachePolicy::new()
+            .path(
+                "/assets/*-*.js",
+                CacheControl::new().public().max_age(31_536_000).immutable(),
+            )
+            .extension("html", CacheControl::new().no_cache())
+            .content_type(
+                "image/*",
+                CacheControl::new()
+                    .private()
+                    .max_age(60)
+                    .stale_while_revalidate(30),
+            )
+            .otherwise(CacheControl::new().public().s_maxage(600).must_revalidate()),
+    ));
+
+    for (path, expected) in [
+        (
+            "/assets/app-1a2b3c.js",
+            "public, max-age=31536000, immutable",
+        ),
+        ("/about.html", "no-cache"),
+        (
+            "/logo.png",
+            "private, max-age=60, stale-while-revalidate=30",
+        ),
+        ("/notes.txt", "public, s-maxage=600, must-revalidate"),
+    ] {
+        let res = harness.get(path).await.unwrap();
+        assert_eq!(res.headers().get(header::CACHE_CONTROL).unwrap(), expected);
+    }
+
+    // Pre-encoded files match by the path of the original.
+    let req = Request::builder()
+        .uri("/docs/index.html")
+        .header(header::ACCEPT_ENCODING, "gzip")
+        .body(())
+        .expect("unable to build request");
+    let res = harness.request(req).await.unwrap();
+    assert_eq!(
+        res.headers().get(header::CONTENT_ENCODING),
+        Some(&Encoding::Gzip.to_header_value())
+    );
+    assert_eq!(
+        res.headers().get(header::CACHE_CONTROL).unwrap(),
+        "no-cache"
+    );
+}
+
 #[tokio::test]
 async fn content_length() {
     let harness = Harness::new(vec![("file1.html", "this is file1")]);
@@ -270,6 +622,102 @@ async fn serves_file_with_new_if_modified_since() {
 }
 
 #[cfg(target_family = "unix")]
+#[tokio::test]
+async fn not_modified_carries_validators() {
+    let harness = Harness::new(vec![("file1.html", "this is file1")]);
+    let res = harness.get("/file1.html").await.unwrap();
+    let full_headers = res.headers().clone();
+
+    let req = Request::builder()
+        .uri("/file1.html")
+        .header(
+            header::IF_NONE_MATCH,
+            full_headers.get(header::ETAG).unwrap(),
+        )
+        .body(())
+        .expect("unable to build request");
+    let res = harness.request(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+    for name in [
+        header::ETAG,
+        header::LAST_MODIFIED,
+        header::CACHE_CONTROL,
+        header::VARY,
+    ] {
+        assert_eq!(res.headers().get(&name), full_headers.get(&name));
+    }
+    for name in [
+        header::CONTENT_LENGTH,
+        header::CONTENT_TYPE,
+        header::ACCEPT_RANGES,
+    ] {
+        assert!(res.headers().get(&name).is_none());
+    }
+}
+
+#[tokio::test]
+async fn evaluates_entity_tag_preconditions() {
+    let harness = Harness::new(vec![("file1.html", "this is file1")]);
+    let res = harness.get("/file1.html").await.unwrap();
+    let etag = res.headers().get(header::ETAG).unwrap().to_str().unwrap();
+    let opaque = etag.strip_prefix("W/").unwrap().to_string();
+    let etag = etag.to_string();
+    let get = |name: header::HeaderName, value: String| {
+        let req = Request::builder()
+            .uri("/file1.html")
+            .header(name, value)
+            .header(header::IF_MODIFIED_SINCE, "Sat, 26 Oct 1985 01:22:00 GMT")
+            .body(())
+            .expect("unable to build request");
+        harness.request(req)
+    };
+
+    // If-None-Match uses weak comparison, handles lists and `*`, and overrides If-Modified-Since.
+    for value in [
+        etag.clone(),
+        opaque.clone(),
+        format!("\"other\", {}", etag),
+        "*".to_string(),
+    ] {
+        let res = get(header::IF_NONE_MATCH, value).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+    }
+    let res = get(header::IF_NONE_MATCH, "\"other\"".to_string())
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    // If-Match uses strong comparison, so our weak tags only match `*`.
+    let res = get(header::IF_MATCH, "*".to_string()).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    for value in [etag.clone(), opaque.clone()] {
+        let res = get(header::IF_MATCH, value).await.unwrap();
+        assert_eq!(res.status(), StatusCode::PRECONDITION_FAILED);
+    }
+}
+
+#[tokio::test]
+async fn evaluates_if_unmodified_since() {
+    let harness = Harness::new(vec![("file1.html", "this is file1")]);
+    let get = |if_unmodified: SystemTime| {
+        let req = Request::builder()
+            .uri("/file1.html")
+            .header(header::IF_UNMODIFIED_SINCE, fmt_http_date(if_unmodified))
+            .body(())
+            .expect("unable to build request");
+        harness.request(req)
+    };
+
+    let res = get(SystemTime::now() - Duration::from_secs(3600))
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::PRECONDITION_FAILED);
+    let res = get(SystemTime::now() + Duration::from_secs(3600))
+        .await
+        .unwrap();
+    assert_eq!(read_body(res).await, "this is file1");
+}
+
 #[tokio::test]
 async fn last_modified_is_gmt() {
     let harness = Harness::new(vec![("file1.html", "this is file1")]);
@@ -312,6 +760,115 @@ async fn no_headers_for_invalid_mtime() {
     assert!(res.headers().get(header::ETAG).is_none());
 }
 
+#[cfg(target_family = "unix")]
+#[tokio::test]
+async fn applies_symlink_policy() {
+    use std::os::unix::fs::symlink;
+
+    let outside = Harness::create_temp_dir(vec![("secret.txt", "this is secret")]);
+    let dir = Harness::create_temp_dir(vec![("file1.html", "this is file1")]);
+    symlink(
+        outside.path().join("secret.txt"),
+        dir.path().join("escape.txt"),
+    )
+    .unwrap();
+    symlink(outside.path(), dir.path().join("escape")).unwrap();
+    symlink("file1.html", dir.path().join("inside.html")).unwrap();
+
+    let serve = |policy| {
+        let mut opener = TokioFileOpener::new(dir.path());
+        opener.symlinks = policy;
+        let mut static_ = Static::with_opener(opener);
+        static_.autoindex(true);
+        move |path: &str| {
+            let req = Request::builder()
+                .uri(path)
+                .body(())
+                .expect("unable to build request");
+            static_.clone().serve(req)
+        }
+    };
+
+    let get = serve(SymlinkPolicy::Follow);
+    let res = get("/escape.txt").await.unwrap();
+    assert_eq!(read_body(res).await, "this is secret");
+
+    let get = serve(SymlinkPolicy::FollowWithinRoot);
+    let res = get("/escape.txt").await.unwrap();
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    let res = get("/escape/secret.txt").await.unwrap();
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    let res = get("/inside.html").await.unwrap();
+    assert_eq!(read_body(res).await, "this is file1");
+    let body = read_body(get("/").await.unwrap()).await;
+    assert!(body.contains("inside.html") && !body.contains("escape"));
+
+    let get = serve(SymlinkPolicy::Never);
+    let res = get("/inside.html").await.unwrap();
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    let res = get("/file1.html").await.unwrap();
+    assert_eq!(read_body(res).await, "this is file1");
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn confines_openat2_opener_to_root() {
+    use hyper_staticfile::vfs::Openat2FileOpener;
+    use std::{io::ErrorKind, os::unix::fs::symlink};
+
+    let outside = Harness::create_temp_dir(vec![("secret.txt", "this is secret")]);
+    let dir = Harness::create_temp_dir(vec![("file1.html", "this is file1")]);
+    symlink(
+        outside.path().join("secret.txt"),
+        dir.path().join("escape.txt"),
+    )
+    .unwrap();
+    symlink("file1.html", dir.path().join("inside.html")).unwrap();
+    fs::create_dir(dir.path().join("sub")).unwrap();
+    symlink("../file1.html", dir.path().join("sub/link.html")).unwrap();
+
+    let serve = |no_symlinks| {
+        let mut opener = match Openat2FileOpener::new(dir.path()) {
+            Ok(opener) => opener,
+            Err(err) if err.kind() == ErrorKind::Unsupported => return None,
+            Err(err) => panic!("failed to create opener: {}", err),
+        };
+        opener.no_symlinks = no_symlinks;
+        let mut static_ = Static::with_opener(opener);
+        static_.autoindex(true);
+        Some(move |path: &str| {
+            let req = Request::builder()
+                .uri(path)
+                .body(())
+                .expect("unable to build request");
+            static_.clone().serve(req)
+        })
+    };
+
+    let get = match serve(false) {
+        Some(get) => get,
+        None => return, // Kernel without `openat2`.
+    };
+    let res = get("/escape.txt").await.unwrap();
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    let res = get("/inside.html").await.unwrap();
+    assert_eq!(read_body(res).await, "this is file1");
+    let body = read_body(get("/").await.unwrap()).await;
+    assert!(body.contains("inside.html") && !body.contains("escape.txt"));
+
+    // Symlinks to a parent directory are fine, as long as they stay beneath the root.
+    let res = get("/sub/link.html").await.unwrap();
+    assert_eq!(read_body(res).await, "this is file1");
+    let body = read_body(get("/sub/").await.unwrap()).await;
+    assert!(body.contains("link.html"));
+
+    let get = serve(true).unwrap();
+    let res = get("/inside.html").await.unwrap();
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    let res = get("/file1.html").await.unwrap();
+    assert_eq!(read_body(res).await, "this is file1");
+}
+
 #[tokio::test]
 async fn serves_file_ranges_beginning() {
     let harness = Harness::new(vec![("file1.html", "this is file1")]);
@@ -418,7 +975,7 @@ async fn serves_file_ranges_if_range_negative() {
 }
 
 #[tokio::test]
-async fn serves_file_ranges_if_range_etag_positive() {
+async fn serves_file_ranges_if_range_weak_etag_negative() {
     let harness = Harness::new(vec![("file1.html", "this is file1")]);
 
     // first request goes out without etag to fetch etag
@@ -438,64 +995,379 @@ async fn serves_file_ranges_if_range_etag_positive() {
         .expect("unable to build request");
 
     let res = harness.request(req).await.unwrap();
-    assert_eq!(read_body(res).await, "is file1");
+    // whole thing comes back since If-Range requires a strong validator
+    assert_eq!(read_body(res).await, "this is file1");
 }
 
 #[tokio::test]
-async fn serves_requested_range_not_satisfiable_when_at_end() {
+async fn serves_file_ranges_if_range_date_positive() {
     let harness = Harness::new(vec![("file1.html", "this is file1")]);
+    let modified = SystemTime::now() - Duration::from_secs(3600);
+    fs::File::options()
+        .write(true)
+        .open(harness.dir.path().join("file1.html"))
+        .and_then(|file| file.set_modified(modified))
+        .expect("failed to set modification time");
+
     let req = Request::builder()
         .uri("/file1.html")
-        .header(header::RANGE, "bytes=13-")
+        .header(header::RANGE, "bytes=5-")
+        .header(header::IF_RANGE, fmt_http_date(modified))
         .body(())
         .expect("unable to build request");
 
     let res = harness.request(req).await.unwrap();
-    assert_eq!(res.status(), hyper::StatusCode::RANGE_NOT_SATISFIABLE);
+    assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(read_body(res).await, "is file1");
 }
 
+#[cfg(feature = "xxh3")]
 #[tokio::test]
-async fn serves_gzip() {
-    let harness = Harness::new(vec![
-        ("file1.html", "this is file1"),
-        ("file1.html.gz", "fake gzip compression"),
-    ]);
+async fn serves_content_hash_etags() {
+    let mut harness = Harness::new(vec![("file1.html", "this is file1")]);
+    harness
+        .static_
+        .content_hash(Some(hyper_staticfile::ContentHash::Xxh3));
+    let expected = format!(
+        "\"{}\"",
+        hyper_staticfile::ContentHash::Xxh3
+            .hash(b"this is file1")
+            .unwrap()
+    );
+
+    let res = harness.get("/file1.html").await.unwrap();
+    assert_eq!(res.headers().get(header::ETAG).unwrap(), expected.as_str());
+
+    // The tag only depends on the contents, and is a strong validator.
+    fs::File::options()
+        .write(true)
+        .open(harness.dir.path().join("file1.html"))
+        .and_then(|file| file.set_modified(SystemTime::now() - Duration::from_secs(3600)))
+        .expect("failed to set modification time");
     let req = Request::builder()
         .uri("/file1.html")
-        .header(header::ACCEPT_ENCODING, "gzip")
+        .header(header::RANGE, "bytes=5-")
+        .header(header::IF_RANGE, expected.as_str())
         .body(())
         .expect("unable to build request");
-
     let res = harness.request(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(res.headers().get(header::ETAG).unwrap(), expected.as_str());
+    assert_eq!(read_body(res).await, "is file1");
+
+    // Changes to the contents are picked up.
+    harness.append("file1.html", "\nthis is file2");
+    let res = harness.get("/file1.html").await.unwrap();
+    let etag = res.headers().get(header::ETAG).unwrap();
+    assert!(!etag.to_str().unwrap().starts_with("W/"));
+    assert_ne!(etag, expected.as_str());
+}
+
+#[cfg(feature = "xxh3")]
+#[tokio::test]
+async fn skips_content_hash_on_errors() {
+    use std::{
+        path::Path,
+        pin::Pin,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    // Fails every open after the first, so the file is served but can't be hashed.
+    struct FailsAfterFirstOpen(MemoryFs, AtomicUsize);
+    impl FileOpener for FailsAfterFirstOpen {
+        type File = <MemoryFs as FileOpener>::File;
+        type Future = Pin<
+            Box<
+                dyn Future<
+                        Output = Result<
+                            hyper_staticfile::vfs::FileWithMetadata<Self::File>,
+                            IoError,
+                        >,
+                    > + Send,
+            >,
+        >;
+
+        fn open(&self, path: &Path) -> Self::Future {
+            if self.1.fetch_add(1, Ordering::SeqCst) == 0 {
+                let fut = self.0.open(path);
+                Box::pin(fut)
+            } else {
+                Box::pin(std::future::ready(Err(IoError::other("read failed"))))
+            }
+        }
+    }
+
+    let mut fs = MemoryFs::default();
+    fs.add(
+        "file1.html",
+        "this is file1".into(),
+        Some(SystemTime::now()),
+    );
+    let mut static_ = Static::with_opener(FailsAfterFirstOpen(fs, AtomicUsize::new(0)));
+    static_.content_hash(Some(hyper_staticfile::ContentHash::Xxh3));
+
+    let res = static_.serve(Request::get("/file1.html").body(()).unwrap());
+    let res = res.await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    let etag = res.headers().get(header::ETAG).unwrap();
+    assert!(etag.to_str().unwrap().starts_with("W/"));
+    assert_eq!(read_body(res).await, "this is file1");
+}
+
+#[cfg(feature = "xxh3")]
+#[tokio::test]
+async fn serves_precomputed_content_hash_etags() {
+    let algo = hyper_staticfile::ContentHash::Xxh3;
+    let mut fs = MemoryFs::default();
+    fs.add("file1.html", "this is file1".into(), None)
+        .precompute_hashes(algo);
+
+    let res = Static::with_opener(fs).serve(Request::get("/file1.html").body(()).unwrap());
+    let res = res.await.unwrap();
     assert_eq!(
-        res.headers().get(header::CONTENT_ENCODING),
-        Some(&Encoding::Gzip.to_header_value())
+        res.headers().get(header::ETAG).unwrap(),
+        format!("\"{}\"", algo.hash(b"this is file1").unwrap()).as_str()
     );
-    assert_eq!(read_body(res).await, "fake gzip compression");
 }
 
 #[tokio::test]
-async fn serves_br() {
-    let harness = Harness::new(vec![
-        ("file1.html", "this is file1"),
-        ("file1.html.br", "fake brotli compression"),
-        ("file1.html.gz", "fake gzip compression"),
-    ]);
+async fn serves_custom_validators() {
+    let mut harness = Harness::new(vec![("file1.html", "this is file1")]);
+    let build_time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    harness
+        .static_
+        .validators(move |file: &ResolvedFile<()>| Validators {
+            etag: Some(format!("\"build-{}\"", file.size)),
+            last_modified: Some(build_time),
+        });
+
+    let res = harness.get("/file1.html").await.unwrap();
+    assert_eq!(res.headers().get(header::ETAG).unwrap(), "\"build-13\"");
+    assert_eq!(
+        res.headers().get(header::LAST_MODIFIED).unwrap(),
+        fmt_http_date(build_time).as_str()
+    );
+
     let req = Request::builder()
         .uri("/file1.html")
-        .header(header::ACCEPT_ENCODING, "br;q=1.0, gzip;q=0.5")
+        .header(header::IF_NONE_MATCH, "\"build-13\"")
         .body(())
         .expect("unable to build request");
-
     let res = harness.request(req).await.unwrap();
-    assert_eq!(
-        res.headers().get(header::CONTENT_ENCODING),
-        Some(&Encoding::Br.to_header_value())
-    );
-    assert_eq!(read_body(res).await, "fake brotli compression");
-}
+    assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
 
-#[tokio::test]
+    // The custom tag is a strong validator.
+    let req = Request::builder()
+        .uri("/file1.html")
+        .header(header::RANGE, "bytes=5-")
+        .header(header::IF_RANGE, "\"build-13\"")
+        .body(())
+        .expect("unable to build request");
+    let res = harness.request(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+
+    // Each encoding has its own tag, even if the provider doesn't distinguish them.
+    fs::write(
+        harness.dir.path().join("file1.html.gz"),
+        "fake gzip compression",
+    )
+    .unwrap();
+    harness
+        .static_
+        .validators(|_: &ResolvedFile<()>| Validators {
+            etag: Some("\"build-1\"".to_string()),
+            last_modified: None,
+        });
+    let get = |harness: &Harness, accept_encoding: &'static str| {
+        let req = Request::builder()
+            .uri("/file1.html")
+            .header(header::ACCEPT_ENCODING, accept_encoding)
+            .body(())
+            .expect("unable to build request");
+        harness.request(req)
+    };
+    let res = get(&harness, "identity").await.unwrap();
+    assert_eq!(res.headers().get(header::ETAG).unwrap(), "\"build-1\"");
+    let res = get(&harness, "gzip").await.unwrap();
+    assert_eq!(res.headers().get(header::ETAG).unwrap(), "\"build-1-gzip\"");
+
+    // Malformed tags are replaced with the default ones.
+    harness
+        .static_
+        .validators(|_: &ResolvedFile<()>| Validators {
+            etag: Some("build-1".to_string()),
+            last_modified: None,
+        });
+    let res = get(&harness, "identity").await.unwrap();
+    let etag = res.headers().get(header::ETAG).unwrap().to_str().unwrap();
+    assert!(etag.starts_with("W/\""));
+}
+
+#[tokio::test]
+async fn caches_absent_paths() {
+    let mut harness = Harness::new(vec![("file1.html", "this is file1")]);
+    let cache = Arc::new(NegativeCache::new(Duration::from_secs(3600)));
+    harness.static_.negative_cache(Some(cache.clone()));
+    let get = || {
+        let req = Request::builder()
+            .uri("/file1.html")
+            .header(header::ACCEPT_ENCODING, "gzip, br, zstd")
+            .body(())
+            .expect("unable to build request");
+        harness.request(req)
+    };
+
+    // The first request probes each variant, the second only opens the file itself.
+    get().await.unwrap();
+    assert_eq!((cache.hits(), cache.misses()), (0, 4));
+    get().await.unwrap();
+    assert_eq!((cache.hits(), cache.misses()), (3, 5));
+
+    // New files are only picked up after invalidation.
+    fs::write(harness.dir.path().join("file1.html.gz"), "fake gzip").unwrap();
+    let res = get().await.unwrap();
+    assert!(res.headers().get(header::CONTENT_ENCODING).is_none());
+    cache.invalidate("file1.html.gz".as_ref());
+    let res = get().await.unwrap();
+    assert_eq!(
+        res.headers().get(header::CONTENT_ENCODING),
+        Some(&Encoding::Gzip.to_header_value())
+    );
+}
+
+#[cfg(target_family = "unix")]
+#[tokio::test]
+async fn caches_open_handles() {
+    let dir = Harness::create_temp_dir(vec![
+        ("file1.html", "this is file1"),
+        ("file2.html", "this is file2"),
+        ("file3.html", "this is file3"),
+    ]);
+    let fs =
+        HandleCacheFs::new(TokioFileOpener::new(dir.path()), 2).validity(Duration::from_secs(3600));
+    let static_ = Static::with_opener(fs);
+    let get = |path: &str, range: Option<&str>| {
+        let mut req = Request::builder().uri(path);
+        if let Some(range) = range {
+            req = req.header(header::RANGE, range);
+        }
+        static_
+            .clone()
+            .serve(req.body(()).expect("unable to build request"))
+    };
+
+    // Requests share the handle, but not the read position.
+    let (full, range) = tokio::join!(
+        get("/file1.html", None),
+        get("/file1.html", Some("bytes=5-"))
+    );
+    assert_eq!(read_body(full.unwrap()).await, "this is file1");
+    assert_eq!(read_body(range.unwrap()).await, "is file1");
+    assert_eq!(static_.resolver.opener.len(), 1);
+
+    // The least recently used handle is closed.
+    get("/file2.html", None).await.unwrap();
+    get("/file3.html", None).await.unwrap();
+    assert_eq!(static_.resolver.opener.len(), 2);
+
+    // A replaced file is only picked up after invalidation.
+    fs::write(dir.path().join("new.html"), "changed file3").unwrap();
+    fs::rename(dir.path().join("new.html"), dir.path().join("file3.html")).unwrap();
+    let res = get("/file3.html", None).await.unwrap();
+    assert_eq!(read_body(res).await, "this is file3");
+    static_.resolver.opener.invalidate("file3.html".as_ref());
+    let res = get("/file3.html", None).await.unwrap();
+    assert_eq!(read_body(res).await, "changed file3");
+
+    // Once the validity expires, changes to the file are picked up.
+    let fs = HandleCacheFs::new(TokioFileOpener::new(dir.path()), 2).validity(Duration::ZERO);
+    let static_ = Static::with_opener(fs);
+    let get = |path: &str| {
+        let req = Request::builder().uri(path).body(()).unwrap();
+        static_.clone().serve(req)
+    };
+    let res = get("/file1.html").await.unwrap();
+    assert_eq!(read_body(res).await, "this is file1");
+    let path = dir.path().join("file1.html");
+    let modified = fs::metadata(&path).unwrap().modified().unwrap();
+    fs::write(&path, "this is file1, rewritten").unwrap();
+    let res = get("/file1.html").await.unwrap();
+    assert_eq!(read_body(res).await, "this is file1, rewritten");
+
+    // This includes a replaced file with the same size and modification time.
+    fs::write(dir.path().join("new.html"), "this is file1, replaced!").unwrap();
+    fs::File::options()
+        .write(true)
+        .open(dir.path().join("new.html"))
+        .and_then(|file| file.set_modified(modified))
+        .unwrap();
+    fs::File::options()
+        .write(true)
+        .open(&path)
+        .and_then(|file| file.set_modified(modified))
+        .unwrap();
+    let res = get("/file1.html").await.unwrap();
+    assert_eq!(read_body(res).await, "this is file1, rewritten");
+    fs::rename(dir.path().join("new.html"), &path).unwrap();
+    let res = get("/file1.html").await.unwrap();
+    assert_eq!(read_body(res).await, "this is file1, replaced!");
+}
+
+#[tokio::test]
+async fn serves_requested_range_not_satisfiable_when_at_end() {
+    let harness = Harness::new(vec![("file1.html", "this is file1")]);
+    let req = Request::builder()
+        .uri("/file1.html")
+        .header(header::RANGE, "bytes=13-")
+        .body(())
+        .expect("unable to build request");
+
+    let res = harness.request(req).await.unwrap();
+    assert_eq!(res.status(), hyper::StatusCode::RANGE_NOT_SATISFIABLE);
+}
+
+#[tokio::test]
+async fn serves_gzip() {
+    let harness = Harness::new(vec![
+        ("file1.html", "this is file1"),
+        ("file1.html.gz", "fake gzip compression"),
+    ]);
+    let req = Request::builder()
+        .uri("/file1.html")
+        .header(header::ACCEPT_ENCODING, "gzip")
+        .body(())
+        .expect("unable to build request");
+
+    let res = harness.request(req).await.unwrap();
+    assert_eq!(
+        res.headers().get(header::CONTENT_ENCODING),
+        Some(&Encoding::Gzip.to_header_value())
+    );
+    assert_eq!(read_body(res).await, "fake gzip compression");
+}
+
+#[tokio::test]
+async fn serves_br() {
+    let harness = Harness::new(vec![
+        ("file1.html", "this is file1"),
+        ("file1.html.br", "fake brotli compression"),
+        ("file1.html.gz", "fake gzip compression"),
+    ]);
+    let req = Request::builder()
+        .uri("/file1.html")
+        .header(header::ACCEPT_ENCODING, "br;q=1.0, gzip;q=0.5")
+        .body(())
+        .expect("unable to build request");
+
+    let res = harness.request(req).await.unwrap();
+    assert_eq!(
+        res.headers().get(header::CONTENT_ENCODING),
+        Some(&Encoding::Br.to_header_value())
+    );
+    assert_eq!(read_body(res).await, "fake brotli compression");
+}
+
+#[tokio::test]
 async fn serves_zstd() {
     let harness = Harness::new(vec![
         ("file1.html", "this is file1"),
@@ -517,6 +1389,405 @@ async fn serves_zstd() {
     assert_eq!(read_body(res).await, "fake zstd compression");
 }
 
+#[tokio::test]
+async fn negotiates_encoding_by_quality() {
+    let mut harness = Harness::new(vec![
+        ("file1.html", "this is file1"),
+        ("file1.html.br", "fake brotli compression"),
+        ("file1.html.gz", "fake gzip compression"),
+    ]);
+    let get = |harness: &Harness, accept_encoding: &'static str| {
+        let req = Request::builder()
+            .uri("/file1.html")
+            .header(header::ACCEPT_ENCODING, accept_encoding)
+            .body(())
+            .expect("unable to build request");
+        harness.request(req)
+    };
+
+    // Client q-values take precedence over server preference.
+    let res = get(&harness, "br;q=0.5, gzip").await.unwrap();
+    assert_eq!(read_body(res).await, "fake gzip compression");
+
+    // A q-value of zero refuses the encoding.
+    let res = get(&harness, "br;q=0, gzip;q=0.1").await.unwrap();
+    assert_eq!(read_body(res).await, "fake gzip compression");
+    let res = get(&harness, "gzip;q=0").await.unwrap();
+    assert_eq!(read_body(res).await, "this is file1");
+
+    // The wildcard matches unlisted encodings.
+    let res = get(&harness, "gzip;q=0.5, *;q=0.8").await.unwrap();
+    assert_eq!(read_body(res).await, "fake brotli compression");
+
+    // Explicitly preferring identity serves the original.
+    let res = get(&harness, "gzip;q=0.5, identity").await.unwrap();
+    assert_eq!(read_body(res).await, "this is file1");
+
+    // Server preference breaks ties.
+    let res = get(&harness, "gzip, br").await.unwrap();
+    assert_eq!(read_body(res).await, "fake brotli compression");
+    harness
+        .static_
+        .allowed_encodings(vec![Encoding::Gzip, Encoding::Br].into_iter().collect());
+    let res = get(&harness, "br, gzip").await.unwrap();
+    assert_eq!(read_body(res).await, "fake gzip compression");
+
+    // Refusing identity without a variant available is not acceptable.
+    let res = get(&harness, "zstd, identity;q=0").await.unwrap();
+    assert_eq!(res.status(), StatusCode::NOT_ACCEPTABLE);
+    let res = get(&harness, "br;q=0.5, *;q=0").await.unwrap();
+    assert_eq!(read_body(res).await, "fake brotli compression");
+}
+
+#[tokio::test]
+async fn serves_custom_encoding() {
+    const DEFLATE: Encoding = Encoding::custom("deflate", ".zz");
+    let mut harness = Harness::new(vec![
+        ("file1.html", "this is file1"),
+        ("file1.html.gz", "fake gzip compression"),
+        ("file1.html.zz", "fake deflate compression"),
+    ]);
+    let mut allowed = AcceptEncoding::all();
+    allowed.insert(DEFLATE);
+    harness.static_.allowed_encodings(allowed);
+    let get = |harness: &Harness, accept_encoding: &'static str| {
+        let req = Request::builder()
+            .uri("/file1.html")
+            .header(header::ACCEPT_ENCODING, accept_encoding)
+            .body(())
+            .expect("unable to build request");
+        harness.request(req)
+    };
+
+    let res = get(&harness, "Deflate").await.unwrap();
+    assert_eq!(
+        res.headers().get(header::CONTENT_ENCODING),
+        Some(&DEFLATE.to_header_value())
+    );
+    assert_eq!(read_body(res).await, "fake deflate compression");
+
+    // Registered encodings have the lowest server preference.
+    let res = get(&harness, "deflate, gzip").await.unwrap();
+    assert_eq!(read_body(res).await, "fake gzip compression");
+    let res = get(&harness, "deflate, gzip;q=0.5").await.unwrap();
+    assert_eq!(read_body(res).await, "fake deflate compression");
+
+    // Encodings built at runtime are equal to their static counterparts.
+    let mut allowed = AcceptEncoding::none();
+    allowed.insert(Encoding::custom_owned(String::from("deflate"), ".zz"));
+    harness.static_.allowed_encodings(allowed);
+    let res = get(&harness, "deflate").await.unwrap();
+    assert_eq!(
+        res.headers().get(header::CONTENT_ENCODING),
+        Some(&DEFLATE.to_header_value())
+    );
+    assert_eq!(read_body(res).await, "fake deflate compression");
+
+    // Unregistered encodings are not served.
+    harness.static_.allowed_encodings(AcceptEncoding::all());
+    let res = get(&harness, "deflate").await.unwrap();
+    assert_eq!(read_body(res).await, "this is file1");
+}
+
+#[tokio::test]
+async fn varies_on_accept_encoding() {
+    let mut harness = Harness::new(vec![
+        ("file1.html", "this is file1"),
+        ("file1.html.gz", "fake gzip compression"),
+    ]);
+    let get =
+        |harness: &Harness, accept_encoding: &'static str, if_modified: Option<SystemTime>| {
+            let mut req = Request::builder()
+                .uri("/file1.html")
+                .header(header::ACCEPT_ENCODING, accept_encoding);
+            if let Some(if_modified) = if_modified {
+                req = req.header(header::IF_MODIFIED_SINCE, fmt_http_date(if_modified));
+            }
+            harness.request(req.body(()).expect("unable to build request"))
+        };
+    let vary = header::HeaderValue::from_static("Accept-Encoding");
+
+    // Both representations vary, and have distinct entity tags.
+    let res = get(&harness, "gzip", None).await.unwrap();
+    assert_eq!(res.headers().get(header::VARY), Some(&vary));
+    let gzip_etag = res.headers().get(header::ETAG).cloned();
+    let res = get(&harness, "identity", None).await.unwrap();
+    assert_eq!(res.headers().get(header::VARY), Some(&vary));
+    let identity_etag = res.headers().get(header::ETAG).cloned();
+    assert!(gzip_etag.is_some() && identity_etag.is_some());
+    assert_ne!(gzip_etag, identity_etag);
+
+    // Not modified responses also vary.
+    let if_modified = SystemTime::now() + Duration::from_secs(3600);
+    let res = get(&harness, "gzip", Some(if_modified)).await.unwrap();
+    assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+    assert_eq!(res.headers().get(header::VARY), Some(&vary));
+
+    // Without negotiation, the response does not vary.
+    harness.static_.allowed_encodings(AcceptEncoding::none());
+    let res = get(&harness, "gzip", None).await.unwrap();
+    assert!(res.headers().get(header::VARY).is_none());
+    assert_eq!(read_body(res).await, "this is file1");
+}
+
+#[cfg(feature = "gzip")]
+#[tokio::test]
+async fn compresses_on_the_fly() {
+    let text = "this is a compressible text file. ".repeat(64);
+    let mut harness = Harness::new(vec![
+        ("file1.txt", &text),
+        ("small.txt", "this is small"),
+        ("image.png", &text),
+        ("pre.txt", &text),
+        ("pre.txt.gz", "fake gzip compression"),
+    ]);
+    harness
+        .static_
+        .compression(Some(hyper_staticfile::Compression::new().min_size(64)));
+    let get = |harness: &Harness, path: &str, range: Option<&str>| {
+        let mut req = Request::builder()
+            .uri(path)
+            .header(header::ACCEPT_ENCODING, "gzip");
+        if let Some(range) = range {
+            req = req.header(header::RANGE, range);
+        }
+        harness.request(req.body(()).expect("unable to build request"))
+    };
+
+    let res = get(&harness, "/file1.txt", None).await.unwrap();
+    assert_eq!(
+        res.headers().get(header::CONTENT_ENCODING),
+        Some(&Encoding::Gzip.to_header_value())
+    );
+    assert!(res.headers().get(header::CONTENT_LENGTH).is_none());
+    assert!(res.headers().get(header::ACCEPT_RANGES).is_none());
+    let body = res.into_body().collect().await.unwrap().to_bytes();
+    assert!(body.len() < text.len());
+    let mut decoded = String::new();
+    flate2::read::GzDecoder::new(&body[..])
+        .read_to_string(&mut decoded)
+        .unwrap();
+    assert_eq!(decoded, text);
+
+    // Files below the minimum size, of other content types, with a pre-encoded variant, or
+    // requested with a range are not compressed.
+    for (path, range) in [
+        ("/small.txt", None),
+        ("/image.png", None),
+        ("/pre.txt", None),
+        ("/file1.txt", Some("bytes=0-3")),
+    ] {
+        let res = get(&harness, path, range).await.unwrap();
+        assert!(res.headers().get(header::CONTENT_LENGTH).is_some());
+        if path != "/pre.txt" {
+            assert!(res.headers().get(header::CONTENT_ENCODING).is_none());
+        }
+    }
+
+    // Clients that refuse the identity encoding get a compressed response, if possible.
+    let get_refusing_identity = |harness: &Harness, path: &str| {
+        let req = Request::builder()
+            .uri(path)
+            .header(header::ACCEPT_ENCODING, "gzip, identity;q=0")
+            .body(())
+            .expect("unable to build request");
+        harness.request(req)
+    };
+    let res = get_refusing_identity(&harness, "/file1.txt").await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(
+        res.headers().get(header::CONTENT_ENCODING),
+        Some(&Encoding::Gzip.to_header_value())
+    );
+    let res = get_refusing_identity(&harness, "/image.png").await.unwrap();
+    assert_eq!(res.status(), StatusCode::NOT_ACCEPTABLE);
+}
+
+#[cfg(feature = "gzip")]
+#[tokio::test]
+async fn serves_encoded_only_files() {
+    let mut harness = Harness::new(vec![]);
+    let mut enc = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    enc.write_all(b"this is app.js").unwrap();
+    let compressed = enc.finish().unwrap();
+    fs::write(harness.dir.path().join("app.js.gz"), &compressed).unwrap();
+    let get = |harness: &Harness, accept_encoding: &'static str| {
+        let req = Request::builder()
+            .uri("/app.js")
+            .header(header::ACCEPT_ENCODING, accept_encoding)
+            .header(header::RANGE, "bytes=0-3")
+            .body(())
+            .expect("unable to build request");
+        harness.request(req)
+    };
+
+    let res = get(&harness, "gzip").await.unwrap();
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+    harness.static_.encoded_only(true);
+    let res = get(&harness, "gzip").await.unwrap();
+    assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(
+        res.headers().get(header::CONTENT_ENCODING),
+        Some(&Encoding::Gzip.to_header_value())
+    );
+    let body = res.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(body, compressed[0..4]);
+
+    // Clients that don't accept the encoding receive the full file, decompressed.
+    let res = get(&harness, "br").await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert!(res.headers().get(header::CONTENT_ENCODING).is_none());
+    assert!(res.headers().get(header::CONTENT_LENGTH).is_none());
+    assert_eq!(
+        res.headers().get(header::CONTENT_TYPE),
+        Some(&header::HeaderValue::from_static(
+            "text/javascript; charset=utf-8"
+        ))
+    );
+    assert_eq!(read_body(res).await, "this is app.js");
+
+    let res = get(&harness, "identity;q=0").await.unwrap();
+    assert_eq!(res.status(), StatusCode::NOT_ACCEPTABLE);
+
+    // Directory index files may also exist only in encoded form.
+    fs::create_dir(harness.dir.path().join("dir")).unwrap();
+    fs::write(harness.dir.path().join("dir/index.html.gz"), &compressed).unwrap();
+    let req = Request::builder()
+        .uri("/dir/")
+        .header(header::ACCEPT_ENCODING, "br")
+        .body(())
+        .expect("unable to build request");
+    let res = harness.request(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert!(res.headers().get(header::CONTENT_ENCODING).is_none());
+    assert_eq!(
+        res.headers().get(header::CONTENT_TYPE),
+        Some(&header::HeaderValue::from_static("text/html"))
+    );
+    assert_eq!(read_body(res).await, "this is app.js");
+}
+
+#[cfg(feature = "gzip")]
+#[tokio::test]
+async fn caches_compressed_variants() {
+    let text = "this is a compressible text file. ".repeat(64);
+    let mut seed = 1u32;
+    let noise: Vec<u8> = (0..1024)
+        .map(|_| {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (seed >> 16) as u8
+        })
+        .collect();
+    let mut fs = MemoryFs::default();
+    fs.add("file1.txt", text.clone().into(), Some(SystemTime::now()));
+    fs.add("noise.txt", noise.into(), Some(SystemTime::now()));
+    let fs = hyper_staticfile::vfs::VariantCacheFs::new(
+        fs,
+        hyper_staticfile::Compression::new(),
+        1024 * 1024,
+    );
+    let mut static_ = Static::with_opener(fs);
+    static_.allowed_encodings(AcceptEncoding::all());
+    let get_path = |path: &str, range: Option<&str>| {
+        let mut req = Request::builder()
+            .uri(path)
+            .header(header::ACCEPT_ENCODING, "gzip");
+        if let Some(range) = range {
+            req = req.header(header::RANGE, range);
+        }
+        static_
+            .clone()
+            .serve(req.body(()).expect("unable to build request"))
+    };
+    let get = |range: Option<&str>| get_path("/file1.txt", range);
+
+    // The first request is served as-is, while the variant is generated in the background.
+    let res = get(None).await.unwrap();
+    assert!(res.headers().get(header::CONTENT_ENCODING).is_none());
+    assert_eq!(read_body(res).await, text);
+
+    let mut attempts = 0;
+    let res = loop {
+        let res = get(None).await.unwrap();
+        if res.headers().get(header::CONTENT_ENCODING).is_some() {
+            break res;
+        }
+        attempts += 1;
+        assert!(attempts < 100, "variant was not generated");
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    };
+    assert_eq!(
+        res.headers().get(header::CONTENT_ENCODING),
+        Some(&Encoding::Gzip.to_header_value())
+    );
+    let body = res.into_body().collect().await.unwrap().to_bytes();
+    let mut decoded = String::new();
+    flate2::read::GzDecoder::new(&body[..])
+        .read_to_string(&mut decoded)
+        .unwrap();
+    assert_eq!(decoded, text);
+
+    // Cached variants have a known length, and support ranges.
+    let res = get(Some("bytes=0-1")).await.unwrap();
+    assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(
+        res.headers().get(header::CONTENT_ENCODING),
+        Some(&Encoding::Gzip.to_header_value())
+    );
+    let partial = res.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(partial, body.slice(0..2));
+
+    // Variants that are not smaller than the original are never served.
+    for _ in 0..10 {
+        let res = get_path("/noise.txt", None).await.unwrap();
+        assert!(res.headers().get(header::CONTENT_ENCODING).is_none());
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+#[cfg(feature = "gzip")]
+#[tokio::test]
+async fn precompresses_memory_fs() {
+    let text = "this is a compressible text file. ".repeat(64);
+    let mut fs = MemoryFs::default();
+    fs.add("file1.txt", text.clone().into(), None)
+        .add("short.txt", "short".into(), None)
+        .add("image.png", text.clone().into(), None);
+    fs.precompress(&hyper_staticfile::Compression::new().min_size(0))
+        .await
+        .expect("precompress failed");
+    let mut static_ = Static::with_opener(fs);
+    static_.allowed_encodings(AcceptEncoding::all());
+    let get = |path: &str| {
+        let req = Request::builder()
+            .uri(path)
+            .header(header::ACCEPT_ENCODING, "gzip")
+            .body(())
+            .expect("unable to build request");
+        static_.clone().serve(req)
+    };
+
+    let res = get("/file1.txt").await.unwrap();
+    assert_eq!(
+        res.headers().get(header::CONTENT_ENCODING),
+        Some(&Encoding::Gzip.to_header_value())
+    );
+    let body = res.into_body().collect().await.unwrap().to_bytes();
+    assert!(body.len() < text.len());
+    let mut decoded = String::new();
+    flate2::read::GzDecoder::new(&body[..])
+        .read_to_string(&mut decoded)
+        .unwrap();
+    assert_eq!(decoded, text);
+
+    // Variants that don't save space, and incompressible types are left out.
+    for path in ["/short.txt", "/image.png"] {
+        let res = get(path).await.unwrap();
+        assert!(res.headers().get(header::CONTENT_ENCODING).is_none());
+    }
+}
+
 #[tokio::test]
 async fn test_memory_fs() {
     let dir = Harness::create_temp_dir(vec![
@@ -545,6 +1816,257 @@ async fn test_memory_fs() {
     assert_eq!(read_body(res).await, "nested index");
 }
 
+#[tokio::test]
+async fn serves_autoindex_listing() {
+    let mut harness = Harness::new(vec![
+        ("dir/a&b.txt", "file with funky chars"),
+        ("dir/has space.html", "file with a space"),
+        ("dir/sub/file1.html", "this is file1"),
+    ]);
+    harness.static_.autoindex(true);
+
+    let res = harness.get("/dir/").await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(
+        res.headers().get(header::CONTENT_TYPE).unwrap(),
+        "text/html; charset=utf-8"
+    );
+
+    let body = read_body(res).await;
+    assert!(body.contains("<title>Index of /dir/</title>"));
+    assert!(body.contains("<a href=\"/\">../</a>"));
+    assert!(body.contains("<a href=\"/dir/a%26b.txt\">a&amp;b.txt</a></td><td>21</td>"));
+    assert!(body.contains("<a href=\"/dir/has%20space.html\">has space.html</a>"));
+
+    // Directories are listed first.
+    let sub = body.find("<a href=\"/dir/sub/\">sub/</a>").unwrap();
+    let file = body.find("a&amp;b.txt").unwrap();
+    assert!(sub < file);
+}
+
+#[tokio::test]
+async fn serves_autoindex_listing_without_trailing_slash() {
+    let mut harness = Harness::new(vec![
+        ("sub dir/file1.html", "this is file1"),
+        ("sub dir/nested/file2.html", "this is file2"),
+    ]);
+    harness.static_.autoindex(true);
+
+    // Redirects are percent-encoded.
+    let res = harness.get("/sub%20dir").await.unwrap();
+    assert_eq!(res.status(), StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(res.headers().get(header::LOCATION).unwrap(), "/sub%20dir/");
+    harness.static_.strip_trailing_slash(true);
+    let res = harness.get("/sub%20dir/file1.html/").await.unwrap();
+    assert_eq!(
+        res.headers().get(header::LOCATION).unwrap(),
+        "/sub%20dir/file1.html"
+    );
+
+    // Links in a listing served in place still resolve.
+    harness.static_.redirect_directories(false);
+    let res = harness.get("/sub%20dir").await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    let body = read_body(res).await;
+    assert!(body.contains("<a href=\"/\">../</a>"));
+    assert!(body.contains("<a href=\"/sub%20dir/file1.html\">file1.html</a>"));
+    assert!(body.contains("<a href=\"/sub%20dir/nested/\">nested/</a>"));
+}
+
+#[tokio::test]
+async fn autoindex_prefers_index_file() {
+    let mut harness = Harness::new(vec![("index.html", "this is index")]);
+    harness.static_.autoindex(true);
+
+    let res = harness.get("/").await.unwrap();
+    assert_eq!(read_body(res).await, "this is index");
+}
+
+#[tokio::test]
+async fn autoindex_memory_fs() {
+    let mut fs = MemoryFs::default();
+    fs.add("file1.html", "this is file1".into(), None).add(
+        "nested/file2.html",
+        "this is file2".into(),
+        None,
+    );
+
+    let mut static_ = Static::with_opener(fs);
+    static_.autoindex(true);
+
+    let req = Request::builder()
+        .uri("/")
+        .body(())
+        .expect("unable to build request");
+    let res = static_.serve(req).await.unwrap();
+    let body = read_body(res).await;
+    assert!(!body.contains("../"));
+    assert!(body.contains("<a href=\"/file1.html\">file1.html</a>"));
+    assert!(body.contains("<a href=\"/nested/\">nested/</a>"));
+    assert!(!body.contains("file2.html"));
+}
+
+#[tokio::test]
+async fn autoindex_requires_read_dir_support() {
+    // An opener that only implements `open`.
+    struct OpenOnly(MemoryFs);
+    impl FileOpener for OpenOnly {
+        type File = <MemoryFs as FileOpener>::File;
+        type Future = <MemoryFs as FileOpener>::Future;
+
+        fn open(&self, path: &std::path::Path) -> Self::Future {
+            self.0.open(path)
+        }
+    }
+
+    let mut fs = MemoryFs::default();
+    fs.add("nested/file1.html", "this is file1".into(), None);
+
+    let mut static_ = Static::with_opener(OpenOnly(fs));
+    static_.autoindex(true);
+
+    let req = Request::builder()
+        .uri("/nested/")
+        .body(())
+        .expect("unable to build request");
+    let res = static_.serve(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn serves_overlay_layers_in_order() {
+    let dir = Harness::create_temp_dir(vec![
+        ("theme.css", "base theme"),
+        ("logo.svg", "base logo"),
+        ("base/index.html", "base index"),
+    ]);
+
+    let mut overrides = MemoryFs::default();
+    overrides
+        .add("theme.css", "customer theme".into(), None)
+        .add("custom/index.html", "custom index".into(), None);
+
+    let mut fs = OverlayFs::new();
+    fs.layer(overrides).layer(TokioFileOpener::new(dir.path()));
+    let mut static_ = Static::with_opener(fs);
+    static_.autoindex(true);
+
+    let get = |path: &str| {
+        let req = Request::builder()
+            .uri(path)
+            .body(())
+            .expect("unable to build request");
+        static_.clone().serve(req)
+    };
+
+    let res = get("/theme.css").await.unwrap();
+    assert_eq!(read_body(res).await, "customer theme");
+
+    let res = get("/logo.svg").await.unwrap();
+    assert_eq!(read_body(res).await, "base logo");
+
+    let res = get("/base").await.unwrap();
+    assert_eq!(res.status(), StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(res.headers().get(header::LOCATION).unwrap(), "/base/");
+
+    let res = get("/custom").await.unwrap();
+    assert_eq!(res.status(), StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(res.headers().get(header::LOCATION).unwrap(), "/custom/");
+
+    let res = get("/base/").await.unwrap();
+    assert_eq!(read_body(res).await, "base index");
+
+    let res = get("/missing.txt").await.unwrap();
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+    let res = get("/").await.unwrap();
+    let body = read_body(res).await;
+    assert_eq!(body.matches("theme.css").count(), 2);
+    assert!(body.contains("<a href=\"/logo.svg\">logo.svg</a>"));
+    assert!(body.contains("<a href=\"/base/\">base/</a>"));
+    assert!(body.contains("<a href=\"/custom/\">custom/</a>"));
+}
+
+#[tokio::test]
+async fn serves_autoindex_json_listing() {
+    let mut harness = Harness::new(vec![
+        ("app.js", "this is app"),
+        ("app.js.br", "fake brotli compression"),
+        ("app.js.gz", "fake gzip compression"),
+        ("app.js.zz", "fake deflate compression"),
+        ("sub/file1.html", "this is file1"),
+    ]);
+    let mut allowed = AcceptEncoding::all();
+    allowed.insert(Encoding::custom("deflate", ".zz"));
+    harness.static_.autoindex(true).allowed_encodings(allowed);
+
+    let req = Request::builder()
+        .uri("/")
+        .header(header::ACCEPT, "application/json")
+        .body(())
+        .expect("unable to build request");
+    let res = harness.request(req).await.unwrap();
+    assert_eq!(
+        res.headers().get(header::CONTENT_TYPE).unwrap(),
+        "application/json"
+    );
+    assert!(res.headers().get(header::LINK).is_none());
+
+    let body = read_body(res).await;
+    assert!(body.starts_with("[{\"name\":\"sub\",\"type\":\"directory\","));
+    assert!(body.contains("{\"name\":\"app.js\",\"type\":\"file\",\"size\":11,\"mtime\":"));
+    assert!(body.contains(",\"variants\":[\"br\",\"gzip\",\"deflate\"]}"));
+
+    // Browsers still get HTML.
+    let req = Request::builder()
+        .uri("/")
+        .header(header::ACCEPT, "text/html,application/json;q=0.9,*/*;q=0.8")
+        .body(())
+        .expect("unable to build request");
+    let res = harness.request(req).await.unwrap();
+    assert_eq!(
+        res.headers().get(header::CONTENT_TYPE).unwrap(),
+        "text/html; charset=utf-8"
+    );
+}
+
+#[tokio::test]
+async fn paginates_autoindex_json_listing() {
+    let mut fs = MemoryFs::default();
+    fs.add("a.txt", "a".into(), None)
+        .add("b.txt", "b".into(), None)
+        .add("c.txt", "c".into(), None);
+
+    let mut static_ = Static::with_opener(fs);
+    static_.autoindex(true).listing_page_size(Some(2));
+
+    let req = Request::builder()
+        .uri("/")
+        .header(header::ACCEPT, "application/json")
+        .body(())
+        .expect("unable to build request");
+    let res = static_.clone().serve(req).await.unwrap();
+    assert_eq!(
+        res.headers().get(header::LINK).unwrap(),
+        "</?cursor=2>; rel=\"next\""
+    );
+    let body = read_body(res).await;
+    assert!(body.contains("\"a.txt\"") && body.contains("\"b.txt\""));
+    assert!(!body.contains("\"c.txt\""));
+
+    let req = Request::builder()
+        .uri("/?cursor=2")
+        .header(header::ACCEPT, "application/json")
+        .body(())
+        .expect("unable to build request");
+    let res = static_.serve(req).await.unwrap();
+    assert!(res.headers().get(header::LINK).is_none());
+    assert_eq!(
+        read_body(res).await,
+        "[{\"name\":\"c.txt\",\"type\":\"file\",\"size\":1,\"mtime\":null,\"variants\":[]}]"
+    );
+}
+
 #[cfg(target_os = "windows")]
 #[tokio::test]
 async fn ignore_windows_drive_letter() {